@@ -1,4 +1,4 @@
-import { writable } from "svelte/store";
+import { get, writable } from "svelte/store";
 
 export const event = writable(null);
 export const votedFor = writable(null);
@@ -157,12 +157,20 @@ export async function questionData(qid, qs) {
 		let arg = qids.join(",");
 		// and go!
 		// TODO: handle failure
-		let data = await fetch(`/api/questions/${arg}`);
+		// hosts also get to see hidden (and pending) questions, which
+		// come back as null for guests
+		let secret = get(event)?.secret;
+		let data = await fetch(`/api/questions/${arg}`, {
+			headers: secret ? { Authorization: `Bearer ${secret}` } : {}
+		});
 		let json = await data.json();
-		// store back to cache
+		// store back to cache, except for questions held back from us,
+		// which may be shown later on
 		questionCache.update((qs) => {
 			for (const [qid, q] of Object.entries(json)) {
-				qs[qid] = q;
+				if (q) {
+					qs[qid] = q;
+				}
 			}
 			return qs;
 		});
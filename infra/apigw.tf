@@ -76,10 +76,36 @@ resource "aws_apigatewayv2_route" "api_event_eid_get" {
 resource "aws_apigatewayv2_route" "api_route" {
   for_each = {
     get_eeq     = "GET /api/event/{eid}/questions",
-    get_eeqs    = "GET /api/event/{eid}/questions/{secret}",
-    post_toggle = "POST /api/event/{eid}/questions/{secret}/{qid}/toggle/{property}",
+    get_present = "GET /api/event/{eid}/present",
+    post_toggle = "POST /api/event/{eid}/questions/{qid}/toggle/{property}",
+    post_mod    = "POST /api/event/{eid}/moderate",
+    get_set     = "GET /api/event/{eid}/settings",
+    patch_set   = "PATCH /api/event/{eid}/settings",
+    get_export  = "GET /api/event/{eid}/export",
+    post_import = "POST /api/event/{eid}/import",
+    post_secret = "POST /api/event/{eid}/secret",
+    get_tokens  = "GET /api/event/{eid}/tokens",
+    post_tokens = "POST /api/event/{eid}/tokens",
+    del_token   = "DELETE /api/event/{eid}/tokens/{name}",
     get_q       = "GET /api/questions/{qids}",
-    post_vote   = "POST /api/vote/{qid}/{updown}",
+    post_vote   = "POST /api/event/{eid}/vote/{qid}/{updown}",
+    # for older clients, which don't say which event the question is in
+    post_vote_q = "POST /api/vote/{qid}/{updown}",
+
+    # deprecated: the same routes with the secret in the path rather than
+    # in the Authorization header
+    get_eeqs         = "GET /api/event/{eid}/questions/{secret}",
+    get_present_old  = "GET /api/event/{eid}/present/{secret}",
+    post_toggle_old  = "POST /api/event/{eid}/questions/{secret}/{qid}/toggle/{property}",
+    post_mod_old     = "POST /api/event/{eid}/moderate/{secret}",
+    get_set_old      = "GET /api/event/{eid}/settings/{secret}",
+    patch_set_old    = "PATCH /api/event/{eid}/settings/{secret}",
+    get_export_old   = "GET /api/event/{eid}/export/{secret}",
+    post_import_old  = "POST /api/event/{eid}/import/{secret}",
+    post_secret_old  = "POST /api/event/{eid}/secret/{secret}",
+    get_tokens_old   = "GET /api/event/{eid}/tokens/{secret}",
+    post_tokens_old  = "POST /api/event/{eid}/tokens/{secret}",
+    del_token_old    = "DELETE /api/event/{eid}/tokens/{secret}/{name}",
   }
 
   api_id    = aws_apigatewayv2_api.www.id
@@ -19,17 +19,44 @@ resource "aws_cloudfront_cache_policy" "cache_when_requested" {
     cookies_config {
       cookie_behavior = "none"
     }
+    # host requests carry their secret in this header, and get different
+    # answers than guests do for the same path
     headers_config {
-      header_behavior = "none"
+      header_behavior = "whitelist"
+      headers {
+        items = ["Authorization"]
+      }
     }
+    # the only query parameter is the change token for question lists
     query_strings_config {
-      query_string_behavior = "none"
+      query_string_behavior = "whitelist"
+      query_strings {
+        items = ["since"]
+      }
     }
     enable_accept_encoding_brotli = true
     enable_accept_encoding_gzip   = true
   }
 }
 
+# The voter cookie isn't part of the cache key, but the API needs it to
+# tell voters apart (see `Voter` in `server/src/vote.rs`).
+resource "aws_cloudfront_origin_request_policy" "forward_voter" {
+  name = "ForwardVoter"
+  cookies_config {
+    cookie_behavior = "whitelist"
+    cookies {
+      items = ["voter"]
+    }
+  }
+  headers_config {
+    header_behavior = "none"
+  }
+  query_strings_config {
+    query_string_behavior = "none"
+  }
+}
+
 resource "aws_cloudfront_function" "index_everywhere" {
   name    = "index-everywhere"
   runtime = "cloudfront-js-2.0"
@@ -95,7 +122,8 @@ resource "aws_cloudfront_distribution" "www" {
     target_origin_id = local.gw_origin_id
     compress         = true
 
-    cache_policy_id = aws_cloudfront_cache_policy.cache_when_requested.id
+    cache_policy_id          = aws_cloudfront_cache_policy.cache_when_requested.id
+    origin_request_policy_id = aws_cloudfront_origin_request_policy.forward_voter.id
     # Using the SecurityHeadersPolicy managed policy ID:
     response_headers_policy_id = "67f7725c-6f97-4210-82d7-5512b31e9d03"
 
@@ -34,10 +34,40 @@ resource "aws_dynamodb_table" "questions" {
     enabled        = true
   }
 
+  # what the API used to list questions by before it needed more attributes projected. a GSI's
+  # projection can't be changed in place, only by deleting and re-creating the index, and queries
+  # against it fail while that happens. so this stays as it was until every running Lambda has
+  # moved on to `top_v2`, and can then be removed (along with its entry in lambda.tf).
   global_secondary_index {
     name               = "top"
     hash_key           = "eid"
     projection_type    = "INCLUDE"
     non_key_attributes = ["answered", "hidden", "votes"]
   }
+
+  # adding an index backfills it in the background. the Lambda's IAM policy refers to it, so the
+  # Lambda isn't updated to use it until it's active.
+  global_secondary_index {
+    name               = "top_v2"
+    hash_key           = "eid"
+    projection_type    = "INCLUDE"
+    non_key_attributes = ["answered", "expire", "hidden", "modified", "pending", "votes"]
+  }
+}
+
+# one item per vote, keyed by "<eid>#<qid>#<voter>", so that votes can't be cast twice
+resource "aws_dynamodb_table" "votes" {
+  name         = "votes"
+  billing_mode = "PAY_PER_REQUEST"
+  hash_key     = "id"
+
+  attribute {
+    name = "id"
+    type = "S"
+  }
+
+  ttl {
+    attribute_name = "expire"
+    enabled        = true
+  }
 }
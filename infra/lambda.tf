@@ -34,11 +34,20 @@ data "aws_iam_policy_document" "dynamodb" {
       "dynamodb:PutItem",
       "dynamodb:GetItem",
       "dynamodb:BatchGetItem",
+      # down-votes delete the vote they take back
+      "dynamodb:DeleteItem",
+      # asking checks the event in the same transaction as it writes the question
+      "dynamodb:ConditionCheckItem",
+      # bulk changes to questions, and imports, go through PartiQL batches
+      "dynamodb:PartiQLUpdate",
+      "dynamodb:PartiQLInsert",
     ]
     resources = [
       aws_dynamodb_table.events.arn,
       aws_dynamodb_table.questions.arn,
-      "${aws_dynamodb_table.questions.arn}/index/top"
+      "${aws_dynamodb_table.questions.arn}/index/top",
+      "${aws_dynamodb_table.questions.arn}/index/top_v2",
+      aws_dynamodb_table.votes.arn,
     ]
   }
 }
@@ -93,6 +102,13 @@ data "archive_file" "lambda" {
   output_path = "lambda_function_payload.zip"
 }
 
+# What the API signs change tokens and voter cookies with. Every instance has to
+# use the same key, or they'd reject what the others hand out.
+resource "random_password" "signing_key" {
+  length  = 64
+  special = false
+}
+
 resource "aws_lambda_function" "www" {
   function_name = "wewerewondering-api"
   role          = aws_iam_role.www.arn
@@ -109,7 +125,8 @@ resource "aws_lambda_function" "www" {
 
   environment {
     variables = {
-      RUST_LOG = "info,tower_http=debug,wewerewondering_api=trace"
+      RUST_LOG    = "info,tower_http=debug,wewerewondering_api=trace"
+      SIGNING_KEY = random_password.signing_key.result
     }
   }
 
@@ -0,0 +1,27 @@
+-- Mirrors the `events` and `questions` DynamoDB tables (see `run-migrations.sh`).
+-- Timestamps are seconds since the UNIX epoch, just like in DynamoDB.
+
+CREATE TABLE events (
+    id TEXT PRIMARY KEY,
+    secret TEXT NOT NULL,
+    "when" BIGINT NOT NULL,
+    expire BIGINT NOT NULL
+);
+
+CREATE INDEX events_expire ON events (expire);
+
+CREATE TABLE questions (
+    id TEXT PRIMARY KEY,
+    eid TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+    text TEXT NOT NULL,
+    who TEXT,
+    "when" BIGINT NOT NULL,
+    expire BIGINT NOT NULL,
+    votes BIGINT NOT NULL CHECK (votes >= 0),
+    hidden BOOLEAN NOT NULL,
+    answered BIGINT
+);
+
+-- the equivalent of the `top` GSI: listing an event's questions is an index-only scan
+CREATE INDEX questions_top ON questions (eid, votes DESC) INCLUDE (id, hidden, answered, expire);
+CREATE INDEX questions_expire ON questions (expire);
@@ -0,0 +1,10 @@
+-- When each question was last changed, so clients can ask for just what changed since they last
+-- looked. Existing questions count as changed when they were asked.
+
+ALTER TABLE questions ADD COLUMN modified BIGINT;
+UPDATE questions SET modified = "when";
+ALTER TABLE questions ALTER COLUMN modified SET NOT NULL;
+
+-- keep listing an index-only scan
+DROP INDEX questions_top;
+CREATE INDEX questions_top ON questions (eid, votes DESC) INCLUDE (id, hidden, answered, expire, modified);
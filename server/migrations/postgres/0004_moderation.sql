@@ -0,0 +1,9 @@
+-- Events can hold back new questions until the host approves them.
+
+ALTER TABLE events ADD COLUMN moderated BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE questions ADD COLUMN pending BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- keep listing an index-only scan
+DROP INDEX questions_top;
+CREATE INDEX questions_top ON questions (eid, votes DESC)
+    INCLUDE (id, hidden, pending, answered, expire, modified);
@@ -0,0 +1,6 @@
+-- Event settings are kept as a JSON document, so that new ones don't need a migration each.
+-- Moderation becomes one of them.
+
+ALTER TABLE events ADD COLUMN settings TEXT NOT NULL DEFAULT '{}';
+UPDATE events SET settings = '{"moderated":true}' WHERE moderated;
+ALTER TABLE events DROP COLUMN moderated;
@@ -0,0 +1,3 @@
+-- Named co-host tokens, as a JSON list of `{"name", "secret", "created"}`.
+
+ALTER TABLE events ADD COLUMN tokens TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,27 @@
+-- Mirrors the `events` and `questions` DynamoDB tables (see `run-migrations.sh`).
+-- Timestamps are seconds since the UNIX epoch, just like in DynamoDB.
+
+CREATE TABLE events (
+    id TEXT PRIMARY KEY NOT NULL,
+    secret TEXT NOT NULL,
+    "when" INTEGER NOT NULL,
+    expire INTEGER NOT NULL
+);
+
+CREATE INDEX events_expire ON events (expire);
+
+CREATE TABLE questions (
+    id TEXT PRIMARY KEY NOT NULL,
+    eid TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+    text TEXT NOT NULL,
+    who TEXT,
+    "when" INTEGER NOT NULL,
+    expire INTEGER NOT NULL,
+    votes INTEGER NOT NULL,
+    hidden BOOLEAN NOT NULL,
+    answered INTEGER
+);
+
+-- the equivalent of the `top` GSI
+CREATE INDEX questions_eid ON questions (eid);
+CREATE INDEX questions_expire ON questions (expire);
@@ -0,0 +1,8 @@
+-- Who currently has a vote counted for which question, so that nobody can vote twice.
+-- This is the equivalent of the `voters` string set on items in the DynamoDB `questions` table.
+
+CREATE TABLE voters (
+    qid TEXT NOT NULL REFERENCES questions (id) ON DELETE CASCADE,
+    voter TEXT NOT NULL,
+    PRIMARY KEY (qid, voter)
+);
@@ -0,0 +1,5 @@
+-- When each question was last changed, so clients can ask for just what changed since they last
+-- looked. Existing questions count as changed when they were asked.
+
+ALTER TABLE questions ADD COLUMN modified INTEGER NOT NULL DEFAULT 0;
+UPDATE questions SET modified = "when";
@@ -0,0 +1,4 @@
+-- Events can hold back new questions until the host approves them.
+
+ALTER TABLE events ADD COLUMN moderated BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE questions ADD COLUMN pending BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- How many times an event's settings have been changed, so that changes can't overwrite each other.
+
+ALTER TABLE events ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
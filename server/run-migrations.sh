@@ -39,7 +39,7 @@ aws dynamodb create-table \
     --attribute-definitions AttributeName=id,AttributeType=S \
     AttributeName=eid,AttributeType=S \
     --key-schema AttributeName=id,KeyType=HASH \
-    --global-secondary-indexes 'IndexName=top,KeySchema=[{AttributeName=eid,KeyType=HASH}],Projection={ProjectionType=INCLUDE,NonKeyAttributes=[answered,hidden,votes]}' \
+    --global-secondary-indexes 'IndexName=top_v2,KeySchema=[{AttributeName=eid,KeyType=HASH}],Projection={ProjectionType=INCLUDE,NonKeyAttributes=[answered,expire,hidden,modified,pending,votes]}' \
     --billing-mode PAY_PER_REQUEST \
     --endpoint-url "${ENDPOINT_URL}" >/dev/null
 
@@ -47,3 +47,16 @@ aws dynamodb update-time-to-live \
     --table-name questions \
     --time-to-live-specification Enabled=true,AttributeName=expire \
     --endpoint-url "${ENDPOINT_URL}" >/dev/null
+
+echo "🗒️ Creating 'votes' table..."
+aws dynamodb create-table \
+    --table-name votes \
+    --attribute-definitions AttributeName=id,AttributeType=S \
+    --key-schema AttributeName=id,KeyType=HASH \
+    --billing-mode PAY_PER_REQUEST \
+    --endpoint-url "${ENDPOINT_URL}" >/dev/null
+
+aws dynamodb update-time-to-live \
+    --table-name votes \
+    --time-to-live-specification Enabled=true,AttributeName=expire \
+    --endpoint-url "${ENDPOINT_URL}" >/dev/null
@@ -0,0 +1,449 @@
+//! Chores for whoever is on call, as run by the `admin` binary.
+//!
+//! Everything here goes through the same [`Backend`] methods as the API does, so it works against
+//! any store.
+
+use crate::store::{self, Event};
+use crate::toggle::ToggleRequest;
+use crate::{export, utils, Backend};
+use std::io::Write;
+use std::time::Duration;
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How far back `events` and `hide` look unless told otherwise.
+const DEFAULT_DAYS: u64 = 7;
+
+const USAGE: &str = "\
+usage: admin <command>
+
+commands:
+  events [days]        list events created in the last `days` days (default 7), newest first
+  questions <eid>      dump all of an event's questions as JSON
+  rotate <eid>         give an event a new host secret, and print it
+  expire <eid>         take an event and its questions out of the API now (the store removes them later)
+  hide <text> [days]   hide questions containing `text` in events from the last `days` days
+  hash-secrets         hash the secrets and tokens of events that still have them in plaintext
+  seed                 (re-)register the test event, if it isn't there (debug builds only)";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Command {
+    Events { days: u64 },
+    Questions { eid: Ulid },
+    Rotate { eid: Ulid },
+    Expire { eid: Ulid },
+    Hide { text: String, days: u64 },
+    HashSecrets,
+    Seed,
+}
+
+impl TryFrom<&[String]> for Command {
+    type Error = String;
+
+    fn try_from(args: &[String]) -> Result<Self, Self::Error> {
+        let args: Vec<_> = args.iter().map(String::as_str).collect();
+        let eid = |eid: &str| Ulid::from_string(eid).map_err(|e| format!("bad event id: {e}"));
+        let days = |days: Option<&&str>| match days {
+            Some(days) => days.parse().map_err(|e| format!("bad number of days: {e}")),
+            None => Ok(DEFAULT_DAYS),
+        };
+        match &args[..] {
+            ["events"] | ["events", _] => Ok(Command::Events {
+                days: days(args.get(1))?,
+            }),
+            ["questions", e] => Ok(Command::Questions { eid: eid(e)? }),
+            ["rotate", e] => Ok(Command::Rotate { eid: eid(e)? }),
+            ["expire", e] => Ok(Command::Expire { eid: eid(e)? }),
+            ["hide", text] | ["hide", text, _] if !text.trim().is_empty() => Ok(Command::Hide {
+                text: text.to_string(),
+                days: days(args.get(2))?,
+            }),
+            ["hash-secrets"] => Ok(Command::HashSecrets),
+            ["seed"] => Ok(Command::Seed),
+            _ => Err(USAGE.to_string()),
+        }
+    }
+}
+
+pub(crate) async fn run(
+    backend: &Backend,
+    cmd: Command,
+    out: &mut impl Write,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match cmd {
+        Command::Events { days } => {
+            for (eid, event) in events(backend, days).await? {
+                let age = backend
+                    .now()
+                    .duration_since(eid.datetime())
+                    .unwrap_or_default();
+                let title = event.settings.title.as_deref().unwrap_or("(untitled)");
+                writeln!(
+                    out,
+                    "{eid}  {:>4}h ago  {:?}  {title}",
+                    age.as_secs() / 3600,
+                    event.settings.state
+                )?;
+            }
+        }
+        Command::Questions { eid } => {
+            let qs = export::questions(backend, &eid).await?;
+            serde_json::to_writer_pretty(&mut *out, &qs)?;
+            writeln!(out)?;
+        }
+        Command::Rotate { eid } => {
+            let secret = rotate(backend, &eid).await?;
+            writeln!(out, "{secret}")?;
+        }
+        Command::Expire { eid } => {
+            expire(backend, &eid).await?;
+            writeln!(out, "expired {eid}")?;
+        }
+        Command::Hide { text, days } => {
+            for (eid, qid, text) in hide(backend, &text, days).await? {
+                writeln!(out, "{eid}  {qid}  {text}")?;
+            }
+        }
+        Command::HashSecrets => {
+            let eids = hash_secrets(backend).await?;
+            for eid in &eids {
+                writeln!(out, "{eid}")?;
+            }
+            writeln!(out, "hashed the credentials of {} events", eids.len())?;
+        }
+        Command::Seed => {
+            #[cfg(debug_assertions)]
+            {
+                let (_, qids) = crate::utils::seed(backend).await;
+                writeln!(out, "test event has {} questions", qids.len())?;
+            }
+            #[cfg(not(debug_assertions))]
+            return Err("seeding is only available in debug builds".into());
+        }
+    }
+    Ok(())
+}
+
+/// The live events created in the last `days` days, newest first.
+pub(crate) async fn events(
+    backend: &Backend,
+    days: u64,
+) -> Result<Vec<(Ulid, Event)>, store::Error> {
+    let since = backend
+        .now()
+        .checked_sub(crate::days(days))
+        .unwrap_or(std::time::UNIX_EPOCH);
+    let mut eids = backend.events(since).await?;
+    eids.sort_unstable_by(|a, b| b.cmp(a));
+    let mut events = Vec::with_capacity(eids.len());
+    for eid in eids {
+        // it may have expired since it was listed
+        if let Some(event) = backend.event(&eid).await? {
+            events.push((eid, event));
+        }
+    }
+    Ok(events)
+}
+
+/// Give an event a new host secret, and return it.
+///
+/// The old secret stops working right away.
+pub(crate) async fn rotate(backend: &Backend, eid: &Ulid) -> Result<String, store::Error> {
+    let secret = crate::new::secret();
+    backend
+        .set_secret(eid, None, &crate::utils::hash_secret(&secret))
+        .await?;
+    warn!(%eid, "rotated event secret");
+    Ok(secret)
+}
+
+/// Make an event and all of its questions expire now.
+///
+/// Every store skips expired items when reading, so they disappear from the API right away. They
+/// stay in the store until it gets around to removing them, which for DynamoDB's TTL can take a
+/// couple of days.
+pub(crate) async fn expire(backend: &Backend, eid: &Ulid) -> Result<(), store::Error> {
+    let event = backend.event(eid).await?.ok_or(store::Error::NotFound)?;
+    // the questions first, since the event has to be live for that
+    backend.retain(eid, Duration::ZERO).await?;
+    backend
+        .configure(eid, event.revision, &event.settings, Some(backend.now()))
+        .await?;
+    warn!(%eid, "force-expired event");
+    Ok(())
+}
+
+/// Hide every question containing `text` (ignoring case) in the events of the last `days` days.
+///
+/// Returns the event id, question id and text of each question that was hidden.
+pub(crate) async fn hide(
+    backend: &Backend,
+    text: &str,
+    days: u64,
+) -> Result<Vec<(Ulid, Ulid, String)>, store::Error> {
+    let needle = text.to_lowercase();
+    let mut hidden = Vec::new();
+    for (eid, _) in events(backend, days).await? {
+        let qids: Vec<_> = backend
+            .list(&eid, true)
+            .await?
+            .into_iter()
+            .filter(|q| !q.hidden)
+            .map(|q| q.id)
+            .collect();
+        for chunk in qids.chunks(export::BATCH) {
+            for q in backend.questions(chunk).await? {
+                if !q.text.to_lowercase().contains(&needle) {
+                    continue;
+                }
+                backend
+                    .toggle(&eid, &q.id, ToggleRequest::Hidden(true))
+                    .await?;
+                warn!(%eid, qid = %q.id, "hid question");
+                hidden.push((eid, q.id, q.text));
+            }
+        }
+    }
+    Ok(hidden)
+}
+
+/// Hash the secrets and tokens of all live events that still store them in plaintext.
+///
+/// Returns the events that had any. Running it again is harmless, since hashed secrets are left
+/// alone. Each write only goes through if what's stored is still the plaintext that was read, so a
+/// secret rotated or a token minted or revoked in the meantime is never overwritten.
+pub(crate) async fn hash_secrets(backend: &Backend) -> Result<Vec<Ulid>, store::Error> {
+    let mut hashed = Vec::new();
+    for eid in backend.events(std::time::UNIX_EPOCH).await? {
+        // it may have expired since it was listed
+        let Some(event) = backend.event(&eid).await? else {
+            continue;
+        };
+        let plaintext = |secret: &str| !utils::is_hashed(secret);
+        let mut changed = false;
+        if plaintext(&event.secret) {
+            let hashed = utils::hash_secret(&event.secret);
+            match backend.set_secret(&eid, Some(&event.secret), &hashed).await {
+                Ok(()) => changed = true,
+                // whatever it was changed to was hashed by whoever changed it
+                Err(store::Error::Conflict) => warn!(%eid, "secret changed while hashing it"),
+                Err(e) => return Err(e),
+            }
+        }
+        if event.tokens.iter().any(|t| plaintext(&t.secret)) {
+            let tokens: Vec<_> = event
+                .tokens
+                .iter()
+                .cloned()
+                .map(|mut t| {
+                    if plaintext(&t.secret) {
+                        t.secret = utils::hash_secret(&t.secret);
+                    }
+                    t
+                })
+                .collect();
+            match backend.set_tokens(&eid, &event.tokens, &tokens).await {
+                Ok(()) => changed = true,
+                // the plaintext ones may be left, but will be hashed by running this again
+                Err(store::Error::Conflict) => warn!(%eid, "tokens changed while hashing them"),
+                Err(e) => return Err(e),
+            }
+        }
+        if changed {
+            info!(%eid, "hashed plaintext credentials");
+            hashed.push(eid);
+        }
+    }
+    Ok(hashed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vote::Voter;
+    use axum::extract::{Path, State};
+    use axum::Json;
+
+    #[test]
+    fn parse() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+        let eid = Ulid::generate();
+        assert_eq!(
+            Command::try_from(&args(&["events"])[..]),
+            Ok(Command::Events { days: 7 })
+        );
+        assert_eq!(
+            Command::try_from(&args(&["events", "30"])[..]),
+            Ok(Command::Events { days: 30 })
+        );
+        assert_eq!(
+            Command::try_from(&args(&["rotate", &eid.to_string()])[..]),
+            Ok(Command::Rotate { eid })
+        );
+        assert_eq!(
+            Command::try_from(&args(&["hide", "spam"])[..]),
+            Ok(Command::Hide {
+                text: "spam".into(),
+                days: 7
+            })
+        );
+        assert!(Command::try_from(&args(&["rotate", "nope"])[..]).is_err());
+        assert!(Command::try_from(&args(&["hide", " "])[..]).is_err());
+        assert!(Command::try_from(&args(&["events", "a", "b"])[..]).is_err());
+        assert_eq!(
+            Command::try_from(&args(&["hash-secrets"])[..]),
+            Ok(Command::HashSecrets)
+        );
+        assert!(Command::try_from(&args(&[])[..]).is_err());
+    }
+
+    async fn inner(backend: Backend) {
+        let mut eids = Vec::new();
+        for title in ["first", "second"] {
+            let settings = Settings {
+                title: Some(title.into()),
+                ..Default::default()
+            };
+            let e = crate::new::new(State(backend.clone()), Some(Json(settings)))
+                .await
+                .unwrap();
+            let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+            for body in ["buy cheap SPAM now", "a fair question"] {
+                let _ = crate::ask::ask(
+                    Path(eid),
+                    State(backend.clone()),
+                    Voter::generate(&backend),
+                    Json(crate::ask::Question {
+                        body: body.into(),
+                        asker: None,
+                    }),
+                )
+                .await
+                .unwrap();
+            }
+            eids.push((eid, e["secret"].as_str().unwrap().to_string()));
+        }
+
+        // the store may have other events in it, but ours are there, newest first
+        let listed: Vec<_> = events(&backend, 1)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(eid, _)| eid)
+            .filter(|eid| eids.iter().any(|(e, _)| e == eid))
+            .collect();
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0] >= listed[1]);
+
+        // hiding only touches matching questions, in every event
+        let hidden = hide(&backend, "spam", 1).await.unwrap();
+        for (eid, _) in &eids {
+            assert_eq!(hidden.iter().filter(|(e, _, _)| e == eid).count(), 1);
+            let qs = export::questions(&backend, eid).await.unwrap();
+            assert_eq!(qs.len(), 2);
+            for q in qs {
+                assert_eq!(q.hidden, q.text.contains("SPAM"));
+            }
+        }
+
+        // after rotating, only the new secret works
+        let (eid, old) = &eids[0];
+        let new = rotate(&backend, eid).await.unwrap();
+        assert_ne!(&new, old);
+        assert!(crate::utils::check_secret(&backend, eid, old)
+            .await
+            .is_err());
+        assert!(crate::utils::check_secret(&backend, eid, &new)
+            .await
+            .is_ok());
+        assert!(matches!(
+            rotate(&backend, &Ulid::generate()).await,
+            Err(store::Error::NotFound)
+        ));
+
+        // events from before secrets were hashed still work, and can be brought up to date
+        let old = Ulid::from_datetime(backend.now());
+        backend
+            .new(&old, "plaintext", &Settings::default())
+            .await
+            .unwrap();
+        let token = store::Token {
+            name: "alice".into(),
+            secret: "also plaintext".into(),
+            created: 0,
+            role: store::Role::Host,
+            show_hidden: false,
+        };
+        backend.set_tokens(&old, &[], &[token]).await.unwrap();
+        assert!(utils::check_secret(&backend, &old, "plaintext")
+            .await
+            .is_ok());
+        assert!(hash_secrets(&backend).await.unwrap().contains(&old));
+        let event = backend.event(&old).await.unwrap().unwrap();
+        assert!(utils::is_hashed(&event.secret));
+        assert!(utils::is_hashed(&event.tokens[0].secret));
+        for secret in ["plaintext", "also plaintext"] {
+            assert!(utils::check_secret(&backend, &old, secret).await.is_ok());
+        }
+        assert!(utils::check_secret(&backend, &old, "wrong").await.is_err());
+
+        // running it again changes nothing, since hashed values are left alone
+        assert!(!hash_secrets(&backend).await.unwrap().contains(&old));
+        let again = backend.event(&old).await.unwrap().unwrap();
+        assert_eq!(again.secret, event.secret);
+        assert!(again.tokens == event.tokens);
+
+        // and a run that read the plaintext before the first one wrote can't overwrite the hash
+        assert!(matches!(
+            backend
+                .set_secret(&old, Some("plaintext"), &utils::hash_secret("plaintext"))
+                .await,
+            Err(store::Error::Conflict)
+        ));
+        assert_eq!(
+            backend.event(&old).await.unwrap().unwrap().secret,
+            event.secret
+        );
+        backend.delete(&old).await;
+
+        // and once expired, the event and its questions are gone
+        let (eid, _) = &eids[1];
+        expire(&backend, eid).await.unwrap();
+        assert!(backend.event(eid).await.unwrap().is_none());
+        assert!(backend.list(eid, true).await.unwrap_or_default().is_empty());
+        assert!(!events(&backend, 1)
+            .await
+            .unwrap()
+            .iter()
+            .any(|(e, _)| e == eid));
+
+        backend.delete(&eids[0].0).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
+}
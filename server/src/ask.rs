@@ -1,72 +1,26 @@
-use super::{Backend, Local};
-use crate::{utils, QUESTIONS_TTL};
-use aws_sdk_dynamodb::{
-    error::SdkError,
-    operation::put_item::{PutItemError, PutItemOutput},
-    types::AttributeValue,
-};
+use super::Backend;
+use crate::hub::Update;
+use crate::settings::Names;
+use crate::store;
+use crate::vote::Voter;
 use axum::extract::{Path, State};
 use axum::response::Json;
 use http::StatusCode;
 use serde::Deserialize;
-use std::{collections::HashMap, time::SystemTime};
 use ulid::Ulid;
 
 #[allow(unused_imports)]
 use tracing::{debug, error, info, trace, warn};
 
-impl Backend {
-    pub(crate) async fn ask(
-        &self,
-        eid: &Ulid,
-        qid: &Ulid,
-        q: Question,
-    ) -> Result<PutItemOutput, SdkError<PutItemError>> {
-        let attrs = [
-            ("id", AttributeValue::S(qid.to_string())),
-            ("eid", AttributeValue::S(eid.to_string())),
-            ("votes", AttributeValue::N(1.to_string())),
-            ("text", AttributeValue::S(q.body)),
-            ("when", utils::to_dynamo_timestamp(SystemTime::now())),
-            (
-                "expire",
-                utils::to_dynamo_timestamp(SystemTime::now() + QUESTIONS_TTL),
-            ),
-            ("hidden", AttributeValue::Bool(false)),
-        ];
-
-        match self {
-            Self::Dynamo(dynamo) => {
-                let mut r = dynamo.put_item().table_name("questions");
-                for (k, v) in attrs {
-                    r = r.item(k, v);
-                }
-                if let Some(asker) = q.asker {
-                    r = r.item("who", AttributeValue::S(asker));
-                }
-                r.send().await
-            }
-            Self::Local(local) => {
-                let mut local = local.lock().unwrap();
-                let Local {
-                    questions,
-                    questions_by_eid,
-                    ..
-                } = &mut *local;
-
-                let mut question = HashMap::from_iter(attrs);
-                if let Some(asker) = q.asker {
-                    question.insert("who", AttributeValue::S(asker));
-                }
-                questions.insert(*qid, question);
-                questions_by_eid
-                    .get_mut(eid)
-                    .expect("adding question to event that doesn't exist")
-                    .push(*qid);
-                Ok(PutItemOutput::builder().build())
-            }
-        }
-    }
+/// The most that a request to ask a question may upload.
+///
+/// This also bounds how much a question can hold when it comes in some other way than as a
+/// request body, such as over a socket, see [`too_long`].
+pub(crate) const MAX_BODY: usize = 1024;
+
+/// Whether a question's text and asker don't fit in [`MAX_BODY`] together.
+pub(crate) fn too_long(text: &str, who: Option<&str>) -> bool {
+    text.len() + who.map_or(0, str::len) > MAX_BODY
 }
 
 #[derive(Deserialize, Debug)]
@@ -78,9 +32,13 @@ pub(crate) struct Question {
 pub async fn ask(
     Path(eid): Path<Ulid>,
     State(dynamo): State<Backend>,
+    voter: Voter,
     q: Json<Question>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    if q.body.trim().is_empty() {
+) -> Result<(Voter, Json<serde_json::Value>), StatusCode> {
+    if too_long(&q.body, q.asker.as_deref()) {
+        warn!(%eid, "rejecting question larger than a request body may be");
+        return Err(http::StatusCode::PAYLOAD_TOO_LARGE);
+    } else if q.body.trim().is_empty() {
         warn!(%eid, "ignoring empty question");
         return Err(http::StatusCode::BAD_REQUEST);
     } else if !q.body.trim().contains(' ') {
@@ -88,15 +46,61 @@ pub async fn ask(
         return Err(http::StatusCode::BAD_REQUEST);
     }
 
-    // TODO: check that eid actually exists
-    let qid = ulid::Ulid::generate();
-    match dynamo.ask(&eid, &qid, q.0).await {
+    let settings = match dynamo.event(&eid).await {
+        Ok(Some(e)) => e.settings,
+        Ok(None) => {
+            warn!(%eid, "attempted to ask question for non-existing event");
+            return Err(http::StatusCode::NOT_FOUND);
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "event request for question failed");
+            return Err(http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let phase = settings.phase(dynamo.now());
+    if !phase.takes_questions() {
+        warn!(%eid, ?phase, "rejecting question for event that isn't open");
+        return Err(http::StatusCode::FORBIDDEN);
+    }
+    let named = q.asker.as_ref().is_some_and(|a| !a.trim().is_empty());
+    match settings.names {
+        Names::Required if !named => {
+            warn!(%eid, "rejecting anonymous question");
+            return Err(http::StatusCode::BAD_REQUEST);
+        }
+        Names::Forbidden if named => {
+            warn!(%eid, "rejecting named question");
+            return Err(http::StatusCode::BAD_REQUEST);
+        }
+        _ => {}
+    }
+    if let Some(max) = settings.max_length {
+        if q.body.trim().chars().count() > max {
+            warn!(%eid, max, "rejecting overly long question");
+            return Err(http::StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let pending = settings.moderated;
+    let qid = ulid::Ulid::from_datetime(dynamo.now());
+    // the asker's implicit first vote counts as theirs, so they can take it back
+    match dynamo
+        .ask(&eid, &qid, &voter.id, q.0, pending, settings.question_ttl())
+        .await
+    {
         Ok(_) => {
-            debug!(%eid, %qid, "created question");
-            Ok(Json(serde_json::json!({ "id": qid.to_string() })))
+            debug!(%eid, %qid, pending, "created question");
+            dynamo.publish(Update::Asked { eid, qid, pending });
+            let q = serde_json::json!({ "id": qid.to_string(), "pending": pending });
+            Ok((voter, Json(q)))
+        }
+        Err(store::Error::NotFound) => {
+            warn!(%eid, "attempted to ask question for non-existing event");
+            Err(http::StatusCode::NOT_FOUND)
         }
         Err(e) => {
-            error!(%eid, %qid, error = %e, "dynamodb request to create question failed");
+            error!(%eid, %qid, error = %e, "request to create question failed");
             Err(http::StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -107,22 +111,116 @@ mod tests {
     use super::*;
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
         let _secret = e["secret"].as_str().unwrap();
         let q = super::ask(
             Path(eid),
             State(backend.clone()),
+            Voter::generate(&backend),
             Json(Question {
                 body: "hello world".into(),
                 asker: Some("person".into()),
             }),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .1;
         let _qid = Ulid::from_string(q["id"].as_str().unwrap()).unwrap();
         // the list test checks that it's actually returned
         backend.delete(&eid).await;
+
+        // asking for a non-existing event gives 404
+        let ask = |eid| {
+            super::ask(
+                Path(eid),
+                State(backend.clone()),
+                Voter::generate(&backend),
+                Json(Question {
+                    body: "hello world".into(),
+                    asker: None,
+                }),
+            )
+        };
+        assert_eq!(ask(eid).await.unwrap_err(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            ask(Ulid::from_string("00000000000000000000000001").unwrap())
+                .await
+                .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn expired() {
+        let clock = crate::clock::Clock::fake(std::time::SystemTime::now());
+        let backend = Backend::local_with_clock(clock.clone());
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        clock.advance(*crate::EVENTS_TTL);
+        let q = super::ask(
+            Path(eid),
+            State(backend.clone()),
+            Voter::generate(&backend),
+            Json(Question {
+                body: "hello world".into(),
+                asker: None,
+            }),
+        )
+        .await;
+        assert_eq!(q.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn restricted() {
+        let backend = Backend::local().await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let ask = async |body: &str, asker: Option<&str>| {
+            super::ask(
+                Path(eid),
+                State(backend.clone()),
+                Voter::generate(&backend),
+                Json(Question {
+                    body: body.into(),
+                    asker: asker.map(String::from),
+                }),
+            )
+            .await
+            .map(|_| ())
+        };
+
+        let mut settings = crate::settings::Settings {
+            names: Names::Required,
+            max_length: Some(11),
+            ..Default::default()
+        };
+        backend.configure(&eid, 0, &settings, None).await.unwrap();
+        assert_eq!(ask("hello world", None).await, Err(StatusCode::BAD_REQUEST));
+        assert_eq!(
+            ask("hello world", Some(" ")).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            ask("hello world!", Some("person")).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(ask("hello world", Some("person")).await, Ok(()));
+
+        settings.names = Names::Forbidden;
+        backend.configure(&eid, 1, &settings, None).await.unwrap();
+        assert_eq!(
+            ask("hello world", Some("person")).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(ask("hello world", None).await, Ok(()));
+
+        // and however it's asked, a question can't be larger than a request body may be
+        settings.max_length = None;
+        backend.configure(&eid, 2, &settings, None).await.unwrap();
+        let long = "hello world ".repeat(MAX_BODY / 12 + 1);
+        assert_eq!(ask(&long, None).await, Err(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(ask(&long[..MAX_BODY], None).await, Ok(()));
     }
 
     #[tokio::test]
@@ -130,9 +228,22 @@ mod tests {
         inner(Backend::local().await).await;
     }
 
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
     #[tokio::test]
     #[ignore]
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
 }
@@ -0,0 +1,38 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+#[cfg(test)]
+use std::time::Duration;
+
+/// A source of "now".
+///
+/// This is the system clock everywhere except in tests, which can use [`Clock::fake`] to get a
+/// clock that only moves when they [advance](Clock::advance) it.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Clock(Option<Arc<Mutex<SystemTime>>>);
+
+impl Clock {
+    pub(crate) fn now(&self) -> SystemTime {
+        match &self.0 {
+            None => SystemTime::now(),
+            Some(now) => *now.lock().unwrap(),
+        }
+    }
+
+    /// A clock that stands still at `start` until advanced.
+    ///
+    /// Clones of the returned clock all share the same time.
+    #[cfg(test)]
+    pub(crate) fn fake(start: SystemTime) -> Self {
+        Self(Some(Arc::new(Mutex::new(start))))
+    }
+
+    /// Move a [fake](Clock::fake) clock forward by `by`.
+    #[cfg(test)]
+    pub(crate) fn advance(&self, by: Duration) {
+        let now = self.0.as_ref().expect("can only advance a fake clock");
+        *now.lock().unwrap() += by;
+    }
+}
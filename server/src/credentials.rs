@@ -0,0 +1,317 @@
+use super::Backend;
+use crate::store::{self, Role, Token};
+use crate::utils::{self, Secret};
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Deserialize;
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How many tokens an event can have at once.
+const MAX_TOKENS: usize = 20;
+
+/// How long a token name can be, in characters.
+const MAX_NAME_LENGTH: usize = 50;
+
+/// How many times a change to the tokens is tried when other changes keep getting in first.
+const MAX_ATTEMPTS: usize = 5;
+
+#[derive(Deserialize, Debug)]
+pub(super) struct NewToken {
+    name: String,
+    #[serde(default)]
+    role: Role,
+    /// Only for presenters: whether they also see hidden questions.
+    #[serde(default)]
+    show_hidden: bool,
+}
+
+fn store_error(eid: &Ulid, e: store::Error) -> StatusCode {
+    match e {
+        store::Error::NotFound => {
+            warn!(%eid, "attempted to change credentials of non-existing event");
+            StatusCode::NOT_FOUND
+        }
+        store::Error::Conflict => {
+            warn!(%eid, "credentials changed under us while changing them");
+            StatusCode::CONFLICT
+        }
+        e => {
+            error!(%eid, error = %e, "request to change event credentials failed");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Give the event a new secret, for when the old one has leaked.
+///
+/// The old secret stops working right away. Co-host tokens are left alone.
+pub(super) async fn rotate(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let event = utils::check_host(&dynamo, &eid, &secret).await?;
+    let secret = crate::new::secret();
+    // if someone else rotated it first, the secret this request was made with is no more
+    dynamo
+        .set_secret(&eid, Some(&event.secret), &utils::hash_secret(&secret))
+        .await
+        .map_err(|e| store_error(&eid, e))?;
+    info!(%eid, "rotated event secret");
+    Ok(Json(serde_json::json!({ "secret": secret })))
+}
+
+/// List the event's tokens, without the tokens themselves.
+pub(super) async fn tokens(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let event = utils::check_host(&dynamo, &eid, &secret).await?;
+    Ok(Json(serde_json::Value::from(
+        event
+            .tokens
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "name": t.name,
+                    "role": t.role,
+                    "show_hidden": t.show_hidden,
+                    "created": t.created,
+                })
+            })
+            .collect::<Vec<_>>(),
+    )))
+}
+
+/// Hand out a new named token, for a co-host or a presenter.
+///
+/// The token is only ever shown here, and only its hash is stored, so the host has to pass it on
+/// right away.
+pub(super) async fn mint(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    State(dynamo): State<Backend>,
+    Json(req): Json<NewToken>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let name = req.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        warn!(%eid, name, "refusing token with bad name");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let token_secret = crate::new::secret();
+    let token = Token {
+        name: name.to_string(),
+        secret: utils::hash_secret(&token_secret),
+        created: utils::to_unix_timestamp(dynamo.now()),
+        role: req.role,
+        show_hidden: req.role == Role::Presenter && req.show_hidden,
+    };
+
+    for attempt in 1.. {
+        // read afresh each time, since a concurrent revoke may have been of our own token
+        let event = utils::check_host(&dynamo, &eid, &secret).await?;
+        if event.tokens.iter().any(|t| t.name == name) {
+            warn!(%eid, name, "refusing token with name that's taken");
+            return Err(StatusCode::CONFLICT);
+        }
+        if event.tokens.len() >= MAX_TOKENS {
+            warn!(%eid, "refusing token beyond the limit");
+            return Err(StatusCode::CONFLICT);
+        }
+        let mut tokens = event.tokens.clone();
+        tokens.push(token.clone());
+        match dynamo.set_tokens(&eid, &event.tokens, &tokens).await {
+            Ok(()) => break,
+            Err(store::Error::Conflict) if attempt < MAX_ATTEMPTS => {
+                debug!(%eid, attempt, "tokens changed while minting, trying again");
+            }
+            Err(e) => return Err(store_error(&eid, e)),
+        }
+    }
+    info!(%eid, name, role = ?token.role, "minted token");
+    Ok(Json(serde_json::json!({
+        "name": token.name,
+        "token": token_secret,
+        "role": token.role,
+        "show_hidden": token.show_hidden,
+        "created": token.created,
+    })))
+}
+
+/// Revoke a token, which stops working right away.
+pub(super) async fn revoke(
+    Path((eid, name)): Path<(Ulid, String)>,
+    Secret(secret): Secret,
+    State(dynamo): State<Backend>,
+) -> Result<StatusCode, StatusCode> {
+    for attempt in 1.. {
+        let event = utils::check_host(&dynamo, &eid, &secret).await?;
+        let mut tokens = event.tokens.clone();
+        tokens.retain(|t| t.name != name);
+        if tokens.len() == event.tokens.len() {
+            warn!(%eid, name, "attempted to revoke non-existing token");
+            return Err(StatusCode::NOT_FOUND);
+        }
+        match dynamo.set_tokens(&eid, &event.tokens, &tokens).await {
+            Ok(()) => break,
+            Err(store::Error::Conflict) if attempt < MAX_ATTEMPTS => {
+                debug!(%eid, attempt, "tokens changed while revoking, trying again");
+            }
+            Err(e) => return Err(store_error(&eid, e)),
+        }
+    }
+    info!(%eid, name, "revoked token");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Secret;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+
+        let mint = async |secret: &str, name: &str| {
+            super::mint(
+                Path(eid),
+                Secret(secret.to_string()),
+                State(backend.clone()),
+                Json(NewToken {
+                    name: name.into(),
+                    role: Role::Host,
+                    show_hidden: false,
+                }),
+            )
+            .await
+            .map(|t| t["token"].as_str().unwrap().to_string())
+        };
+        let alice = mint(&secret, "alice").await.unwrap();
+        let bob = mint(&secret, " bob ").await.unwrap();
+        assert_eq!(mint(&secret, "bob").await, Err(StatusCode::CONFLICT));
+        assert_eq!(mint(&secret, "  ").await, Err(StatusCode::BAD_REQUEST));
+
+        // only hashes are stored
+        let event = backend.event(&eid).await.unwrap().unwrap();
+        assert!(utils::is_hashed(&event.secret));
+        assert!(event.tokens.iter().all(|t| utils::is_hashed(&t.secret)));
+
+        // co-hosts can do host things, but not manage credentials
+        assert!(utils::check_secret(&backend, &eid, &alice).await.is_ok());
+        assert_eq!(mint(&alice, "eve").await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(
+            utils::check_secret(&backend, &eid, "wrong")
+                .await
+                .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let listed = super::tokens(Path(eid), Secret(secret.clone()), State(backend.clone()))
+            .await
+            .unwrap();
+        let names: Vec<_> = listed
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["alice", "bob"]);
+        assert!(listed[0].get("token").is_none());
+
+        // revoking one token leaves the other
+        let revoke = async |name: &str| {
+            super::revoke(
+                Path((eid, name.to_string())),
+                Secret(secret.clone()),
+                State(backend.clone()),
+            )
+            .await
+        };
+        assert_eq!(revoke("alice").await, Ok(StatusCode::NO_CONTENT));
+        assert_eq!(revoke("alice").await, Err(StatusCode::NOT_FOUND));
+        assert!(utils::check_secret(&backend, &eid, &alice).await.is_err());
+        assert!(utils::check_secret(&backend, &eid, &bob).await.is_ok());
+
+        // a presenter minted while a token is being revoked isn't lost to the revoke, and a
+        // revoked token isn't brought back by a mint that read the tokens before the revoke
+        let present = async |name: &str| {
+            super::mint(
+                Path(eid),
+                Secret(secret.clone()),
+                State(backend.clone()),
+                Json(NewToken {
+                    name: name.into(),
+                    role: Role::Presenter,
+                    show_hidden: false,
+                }),
+            )
+            .await
+            .map(|t| t["token"].as_str().unwrap().to_string())
+        };
+        let stage = present("stage").await.unwrap();
+        let read = backend.event(&eid).await.unwrap().unwrap().tokens;
+        present("screen").await.unwrap();
+        let revoked: Vec<_> = read.iter().filter(|t| t.name != "stage").cloned().collect();
+        assert!(matches!(
+            backend.set_tokens(&eid, &read, &revoked).await,
+            Err(store::Error::Conflict)
+        ));
+        let read = backend.event(&eid).await.unwrap().unwrap().tokens;
+        assert_eq!(revoke("stage").await, Ok(StatusCode::NO_CONTENT));
+        let mut minted = read.clone();
+        minted.push(Token {
+            name: "wall".into(),
+            ..read[0].clone()
+        });
+        assert!(matches!(
+            backend.set_tokens(&eid, &read, &minted).await,
+            Err(store::Error::Conflict)
+        ));
+        assert!(utils::check_secret(&backend, &eid, &stage).await.is_err());
+        let event = backend.event(&eid).await.unwrap().unwrap();
+        let names: Vec<_> = event.tokens.iter().map(|t| &*t.name).collect();
+        assert_eq!(names, ["bob", "screen"]);
+
+        // and rotating replaces the secret, but not the tokens
+        let rotated = super::rotate(Path(eid), Secret(secret.clone()), State(backend.clone()))
+            .await
+            .unwrap();
+        let rotated = rotated["secret"].as_str().unwrap();
+        assert!(utils::check_secret(&backend, &eid, &secret).await.is_err());
+        assert!(utils::check_secret(&backend, &eid, rotated).await.is_ok());
+        assert!(utils::check_secret(&backend, &eid, &bob).await.is_ok());
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
+}
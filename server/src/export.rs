@@ -0,0 +1,389 @@
+use super::Backend;
+use crate::store;
+use crate::utils::{self, Secret};
+use axum::extract::{Path, Query, State};
+use axum::response::{AppendHeaders, IntoResponse, Response};
+use http::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+// DynamoDB won't batch-get more items than this in one go.
+pub(crate) const BATCH: usize = 100;
+
+#[derive(Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Format {
+    #[default]
+    Json,
+    Csv,
+    Md,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub(super) struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+}
+
+/// A question as it appears in an export.
+///
+/// Times are in seconds since the UNIX epoch.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Exported {
+    pub text: String,
+    pub who: Option<String>,
+    pub votes: usize,
+    pub when: u64,
+    pub answered: Option<u64>,
+    pub hidden: bool,
+}
+
+/// Everything that was asked at an event, for the host to keep.
+///
+/// Questions that are still waiting for approval are left out, since they were never part of the
+/// event. The Markdown version also leaves out hidden questions, as it's meant for sharing.
+pub(super) async fn export(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    Query(query): Query<ExportQuery>,
+    State(dynamo): State<Backend>,
+) -> Result<Response, StatusCode> {
+    let event = utils::check_secret(&dynamo, &eid, &secret).await?;
+
+    let qs = match questions(&dynamo, &eid).await {
+        Ok(qs) => qs,
+        Err(store::Error::NotFound) => {
+            warn!(%eid, "attempted to export non-existing event");
+            return Err(http::StatusCode::NOT_FOUND);
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "request to export questions failed");
+            return Err(http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    debug!(%eid, format = ?query.format, n = qs.len(), "exporting questions");
+
+    let (content_type, extension, body) = match query.format {
+        Format::Json => (
+            "application/json",
+            "json",
+            serde_json::to_string_pretty(&qs).expect("questions serialize"),
+        ),
+        Format::Csv => ("text/csv; charset=utf-8", "csv", csv(&qs)),
+        Format::Md => (
+            "text/markdown; charset=utf-8",
+            "md",
+            markdown(event.settings.title.as_deref(), qs),
+        ),
+    };
+    let disposition = format!("attachment; filename=\"{eid}.{extension}\"");
+    Ok((
+        AppendHeaders([
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::CACHE_CONTROL, "no-cache".to_string()),
+        ]),
+        body,
+    )
+        .into_response())
+}
+
+/// All of an event's questions, oldest first.
+///
+/// This is the same list-then-batch-get that the host view does.
+pub(crate) async fn questions(dynamo: &Backend, eid: &Ulid) -> Result<Vec<Exported>, store::Error> {
+    let listed: HashMap<_, _> = dynamo
+        .list(eid, true)
+        .await?
+        .into_iter()
+        .filter(|q| !q.pending)
+        .map(|q| (q.id, q))
+        .collect();
+    let mut qids: Vec<_> = listed.keys().copied().collect();
+    qids.sort();
+    let mut qs = Vec::with_capacity(qids.len());
+    for chunk in qids.chunks(BATCH) {
+        for q in dynamo.questions(chunk).await? {
+            let Some(l) = listed.get(&q.id) else {
+                continue;
+            };
+            qs.push(Exported {
+                text: q.text,
+                who: q.who,
+                votes: l.votes,
+                when: utils::to_unix_timestamp(q.when),
+                answered: l.answered.map(utils::to_unix_timestamp),
+                hidden: l.hidden,
+            });
+        }
+    }
+    qs.sort_by_key(|q| q.when);
+    Ok(qs)
+}
+
+// Spreadsheets take cells that start with one of these to be formulas, even in a CSV file.
+const FORMULA: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+/// Keep spreadsheets from taking `cell`, which a guest wrote, for a formula.
+///
+/// That is done by putting a `'` in front, which spreadsheets don't show. Cells that already
+/// start with a `'` get one too, so that [`unescape_cell`] can always take the first one away.
+fn escape_cell(cell: &str) -> String {
+    if cell.starts_with(FORMULA) || cell.starts_with('\'') {
+        format!("'{cell}")
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Undo [`escape_cell`], for importing a CSV export.
+pub(crate) fn unescape_cell(cell: String) -> String {
+    match cell.strip_prefix('\'') {
+        Some(rest) => rest.to_string(),
+        None => cell,
+    }
+}
+
+fn csv(qs: &[Exported]) -> String {
+    let mut w = csv::Writer::from_writer(Vec::new());
+    for q in qs {
+        let q = Exported {
+            text: escape_cell(&q.text),
+            who: q.who.as_deref().map(escape_cell),
+            ..q.clone()
+        };
+        w.serialize(q).expect("writing to a Vec doesn't fail");
+    }
+    String::from_utf8(w.into_inner().expect("writing to a Vec doesn't fail"))
+        .expect("CSV of strings is UTF-8")
+}
+
+/// Keep Markdown from making anything of `text`, which guests wrote, and keep it to one line.
+///
+/// Text is put in a list item, so headings and the like can only start at its very beginning.
+fn escape_markdown(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    // digits followed by a `.` or `)` make a numbered list
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let mut md = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        let special = match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '!' | '|' | '~' | '&' => true,
+            '#' | '+' | '-' | '=' => i == 0,
+            '.' | ')' => i == digits && i > 0,
+            _ => false,
+        };
+        if special {
+            md.push('\\');
+        }
+        md.push(c);
+    }
+    md
+}
+
+fn markdown(title: Option<&str>, qs: Vec<Exported>) -> String {
+    let (mut answered, mut unanswered): (Vec<_>, Vec<_>) = qs
+        .into_iter()
+        .filter(|q| !q.hidden)
+        .partition(|q| q.answered.is_some());
+    answered.sort_by_key(|q| q.answered);
+    unanswered.sort_by_key(|q| std::cmp::Reverse(q.votes));
+
+    let mut md = format!("# {}\n", title.map_or("Questions".into(), escape_markdown));
+    for (heading, qs) in [("Answered", answered), ("Unanswered", unanswered)] {
+        md.push_str(&format!("\n## {heading}\n\n"));
+        if qs.is_empty() {
+            md.push_str("None.\n");
+        }
+        for q in qs {
+            let text = escape_markdown(&q.text);
+            let votes = if q.votes == 1 { "vote" } else { "votes" };
+            md.push_str(&format!("- {text} ({} {votes}", q.votes));
+            if let Some(who) = q.who {
+                md.push_str(&format!(", asked by {}", escape_markdown(&who)));
+            }
+            md.push_str(")\n");
+        }
+    }
+    md
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::Property;
+    use crate::utils::Secret;
+    use crate::vote::Voter;
+    use axum::Json;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let mut qids = Vec::new();
+        for (body, asker) in [
+            ("hello world", None),
+            ("hello moon", Some("person")),
+            ("hello sun", None),
+        ] {
+            let (_, q) = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                Voter::generate(&backend),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: asker.map(String::from),
+                }),
+            )
+            .await
+            .unwrap();
+            qids.push(Ulid::from_string(q["id"].as_str().unwrap()).unwrap());
+        }
+        let _ = crate::vote::vote(
+            Path((eid, qids[1], crate::vote::UpDown::Up)),
+            State(backend.clone()),
+            Voter::generate(&backend),
+        )
+        .await
+        .unwrap();
+        for (qid, property) in [(qids[0], Property::Answered), (qids[2], Property::Hidden)] {
+            let _ = crate::toggle::toggle(
+                Path((eid, qid, property)),
+                Secret(secret.to_string()),
+                State(backend.clone()),
+                "on".into(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let export = async |format| {
+            let res = super::export(
+                Path(eid),
+                Secret(secret.to_string()),
+                Query(ExportQuery { format }),
+                State(backend.clone()),
+            )
+            .await
+            .unwrap();
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        // questions asked within the same second can come in any order
+        let mut qs: Vec<Exported> = serde_json::from_str(&export(Format::Json).await).unwrap();
+        qs.sort_by(|a, b| a.text.cmp(&b.text));
+        assert_eq!(qs.len(), 3);
+        assert_eq!(qs[0].text, "hello moon");
+        assert_eq!(qs[0].who.as_deref(), Some("person"));
+        assert_eq!(qs[0].votes, 2);
+        assert_eq!(qs[1].text, "hello sun");
+        assert!(qs[1].hidden);
+        assert_eq!(qs[2].text, "hello world");
+        assert!(qs[2].answered.is_some());
+
+        let csv = export(Format::Csv).await;
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("text,who,votes,when,answered,hidden"));
+        assert!(lines.any(|l| l.starts_with("hello moon,person,2,")));
+
+        let md = export(Format::Md).await;
+        assert_eq!(
+            md,
+            "# Questions\n\n\
+             ## Answered\n\n\
+             - hello world (1 vote)\n\n\
+             ## Unanswered\n\n\
+             - hello moon (2 votes, asked by person)\n"
+        );
+
+        // and only the host gets to export
+        let res = super::export(
+            Path(eid),
+            Secret("wrong".into()),
+            Query(ExportQuery::default()),
+            State(backend.clone()),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        backend.delete(&eid).await;
+    }
+
+    #[test]
+    fn escaping() {
+        let q = |text: &str, who: Option<&str>| Exported {
+            text: text.into(),
+            who: who.map(String::from),
+            votes: 1,
+            when: 0,
+            answered: None,
+            hidden: false,
+        };
+        let qs = vec![
+            q(
+                "# [click](https://example.com) ![x](y) <b>hi</b>",
+                Some("new\nline"),
+            ),
+            q("1. *not* a list", Some("- not a list either")),
+            q("=HYPERLINK(\"https://example.com\")", Some("@SUM(A1)")),
+            q("'quoted", None),
+        ];
+
+        assert_eq!(
+            markdown(Some("Q&A"), qs.clone()),
+            "# Q\\&A\n\n\
+             ## Answered\n\n\
+             None.\n\n\
+             ## Unanswered\n\n\
+             - \\# \\[click\\](https://example.com) \\!\\[x\\](y) \\<b\\>hi\\</b\\> (1 vote, asked by new line)\n\
+             - 1\\. \\*not\\* a list (1 vote, asked by \\- not a list either)\n\
+             - \\=HYPERLINK(\"https://example.com\") (1 vote, asked by @SUM(A1))\n\
+             - 'quoted (1 vote)\n"
+        );
+
+        let csv = csv(&qs);
+        assert!(csv.contains("\n\"'=HYPERLINK("), "{csv}");
+        assert!(csv.contains(",'@SUM(A1),"), "{csv}");
+        assert!(csv.contains(",'- not a list either,"), "{csv}");
+        assert!(csv.contains("\n''quoted,"), "{csv}");
+        // and importing the export gives back what was asked
+        let now = std::time::SystemTime::now();
+        assert_eq!(
+            crate::import::parse(crate::import::Format::Csv, &csv, now).unwrap(),
+            qs
+        );
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{store, toggle::ToggleRequest, utils, Backend};
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+// Updates are tiny, and a subscriber that falls this far behind is better off starting over.
+const CAPACITY: usize = 1024;
+
+/// A change to a question, as published by the handler that made it.
+#[derive(Debug, Clone)]
+pub(crate) enum Update {
+    Asked {
+        eid: Ulid,
+        qid: Ulid,
+        pending: bool,
+    },
+    Voted {
+        qid: Ulid,
+        votes: usize,
+    },
+    Toggled {
+        eid: Ulid,
+        qid: Ulid,
+        req: ToggleRequest,
+    },
+}
+
+/// Fans out [`Update`]s to everyone that is following along live.
+///
+/// This only reaches subscribers in the same process, which is fine for the standalone server,
+/// but means that Lambda-hosted instances can't offer live updates.
+#[derive(Debug, Clone)]
+pub(crate) struct Hub(broadcast::Sender<Update>);
+
+impl Default for Hub {
+    fn default() -> Self {
+        Hub(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Hub {
+    pub(crate) fn publish(&self, update: Update) {
+        // an error just means that no-one is listening at the moment
+        let _ = self.0.send(update);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Update> {
+        self.0.subscribe()
+    }
+}
+
+/// The part of the [`Hub`]'s updates that a single client of a single event gets to see.
+///
+/// Votes don't say which event they're for, so this keeps track of the event's questions (and
+/// which of them guests can see) to know which updates to pass on.
+#[derive(Debug)]
+pub(crate) struct Feed {
+    eid: Ulid,
+    has_secret: bool,
+    updates: broadcast::Receiver<Update>,
+    questions: HashMap<Ulid, Visibility>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Visibility {
+    hidden: bool,
+    pending: bool,
+}
+
+impl Visibility {
+    fn public(self) -> bool {
+        !self.hidden && !self.pending
+    }
+}
+
+impl Feed {
+    /// Start following `eid`.
+    ///
+    /// Hidden and pending questions are only included if `has_secret` is set.
+    pub(crate) async fn new(
+        backend: &Backend,
+        eid: Ulid,
+        has_secret: bool,
+    ) -> Result<Self, store::Error> {
+        // subscribe first, so that nothing falls between the cracks while we list
+        let updates = backend.subscribe();
+        let questions = backend
+            .list(&eid, true)
+            .await?
+            .into_iter()
+            .map(|q| {
+                let v = Visibility {
+                    hidden: q.hidden,
+                    pending: q.pending,
+                };
+                (q.id, v)
+            })
+            .collect();
+        Ok(Feed {
+            eid,
+            has_secret,
+            updates,
+            questions,
+        })
+    }
+
+    /// Wait for the next update that this client should see.
+    ///
+    /// Each update comes with a name for its kind (`ask`, `vote`, `hidden`, `answered`, or
+    /// `approved`) and its details. Gives `None` if this client fell so far behind that it missed updates, in
+    /// which case it should start over.
+    pub(crate) async fn next(&mut self) -> Option<(&'static str, serde_json::Value)> {
+        loop {
+            let update = match self.updates.recv().await {
+                Ok(update) => update,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!(eid = %self.eid, n, "live feed fell behind");
+                    return None;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            if let Some(message) = self.filter(update) {
+                return Some(message);
+            }
+        }
+    }
+
+    fn filter(&mut self, update: Update) -> Option<(&'static str, serde_json::Value)> {
+        match update {
+            Update::Asked { eid, qid, pending } if eid == self.eid => {
+                let v = Visibility {
+                    hidden: false,
+                    pending,
+                };
+                self.questions.insert(qid, v);
+                (self.has_secret || v.public()).then(|| {
+                    let v = serde_json::json!({ "qid": qid.to_string(), "pending": pending });
+                    ("ask", v)
+                })
+            }
+            Update::Voted { qid, votes } => {
+                let v = *self.questions.get(&qid)?;
+                (self.has_secret || v.public()).then(|| {
+                    let v = serde_json::json!({ "qid": qid.to_string(), "votes": votes });
+                    ("vote", v)
+                })
+            }
+            Update::Toggled { eid, qid, req } if eid == self.eid => {
+                let v = self.questions.get_mut(&qid)?;
+                match req {
+                    // guests also need to know, so they can drop (or bring back) the question
+                    ToggleRequest::Hidden(set) => {
+                        v.hidden = set;
+                        (self.has_secret || !v.pending).then(|| {
+                            let v = serde_json::json!({ "qid": qid.to_string(), "hidden": set });
+                            ("hidden", v)
+                        })
+                    }
+                    ToggleRequest::Answered(time) => (self.has_secret || v.public()).then(|| {
+                        let answered = time.map(utils::to_unix_timestamp);
+                        let v = serde_json::json!({ "qid": qid.to_string(), "answered": answered });
+                        ("answered", v)
+                    }),
+                    ToggleRequest::Approved(set) => {
+                        v.pending = !set;
+                        if self.has_secret {
+                            let v = serde_json::json!({ "qid": qid.to_string(), "approved": set });
+                            Some(("approved", v))
+                        } else if v.hidden {
+                            None
+                        } else if set {
+                            // to guests, an approved question is as good as new
+                            let v = serde_json::json!({ "qid": qid.to_string(), "pending": false });
+                            Some(("ask", v))
+                        } else {
+                            let v = serde_json::json!({ "qid": qid.to_string(), "hidden": true });
+                            Some(("hidden", v))
+                        }
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+}
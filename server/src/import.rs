@@ -0,0 +1,467 @@
+use super::Backend;
+use crate::ask;
+use crate::export::{self, Exported};
+use crate::hub::Update;
+use crate::store::{self, ImportedQuestion};
+use crate::utils::{self, Secret};
+use axum::extract::{Path, Query, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The most questions that are imported in one go.
+const MAX_QUESTIONS: usize = 1000;
+
+/// The largest import body that's accepted.
+pub(crate) const MAX_BODY: usize = 1024 * 1024;
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+    /// A LiveAsk JSON export.
+    LiveAsk,
+    /// A Slido CSV export of questions.
+    Slido,
+    /// Our own JSON export (see [`crate::export`]).
+    Json,
+    /// Our own CSV export (see [`crate::export`]).
+    Csv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::from(s))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct ImportQuery {
+    format: Format,
+}
+
+#[derive(Deserialize, Debug)]
+struct LiveAskQuestion {
+    likes: usize,
+    text: String,
+    hidden: bool,
+    answered: bool,
+    #[serde(rename = "createTimeUnix")]
+    created: u64,
+}
+
+/// The columns of a Slido question export that we care about.
+///
+/// Slido doesn't say when a question was answered, only whether it was archived (which is what
+/// hosts do with answered questions), so they are taken to have been answered at import time.
+#[derive(Deserialize, Debug)]
+struct SlidoQuestion {
+    #[serde(rename = "Question")]
+    text: String,
+    #[serde(rename = "Author", default)]
+    who: Option<String>,
+    #[serde(rename = "Likes", alias = "Upvotes", alias = "Votes", default)]
+    votes: usize,
+    #[serde(rename = "Date", default)]
+    date: Option<String>,
+    #[serde(rename = "Status", default)]
+    status: Option<String>,
+}
+
+/// Read the questions in `input`.
+///
+/// Anything the input doesn't say about when things happened is taken to be `now`.
+pub(crate) fn parse(
+    format: Format,
+    input: &str,
+    now: SystemTime,
+) -> Result<Vec<Exported>, Box<dyn std::error::Error + Send + Sync>> {
+    let now = utils::to_unix_timestamp(now);
+    let qs: Vec<Exported> = match format {
+        Format::LiveAsk => serde_json::from_str::<Vec<LiveAskQuestion>>(input)?
+            .into_iter()
+            .map(|q| Exported {
+                text: q.text,
+                who: None,
+                votes: q.likes,
+                when: q.created,
+                answered: q.answered.then_some(now),
+                hidden: q.hidden,
+            })
+            .collect(),
+        Format::Slido => csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<SlidoQuestion>()
+            .map(|q| {
+                let q = q?;
+                let status = q.status.unwrap_or_default().to_lowercase();
+                Ok(Exported {
+                    text: q.text,
+                    // Slido names askers that didn't give a name "Anonymous"
+                    who: q.who.filter(|who| !who.is_empty() && who != "Anonymous"),
+                    votes: q.votes,
+                    when: q.date.as_deref().and_then(parse_date).unwrap_or(now),
+                    answered: matches!(&*status, "archived" | "answered").then_some(now),
+                    hidden: matches!(&*status, "hidden" | "deleted" | "dismissed"),
+                })
+            })
+            .collect::<Result<_, csv::Error>>()?,
+        Format::Json => serde_json::from_str(input)?,
+        Format::Csv => csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Exported>()
+            .map(|q| {
+                let q = q?;
+                Ok(Exported {
+                    text: export::unescape_cell(q.text),
+                    who: q.who.map(export::unescape_cell),
+                    ..q
+                })
+            })
+            .collect::<Result<_, csv::Error>>()?,
+    };
+    // which also keeps out times too far out to represent
+    if qs
+        .iter()
+        .any(|q| q.when > now || q.answered.is_some_and(|a| a > now))
+    {
+        return Err("questions can't be from the future".into());
+    }
+    Ok(qs)
+}
+
+/// Parse a `YYYY-MM-DD HH:MM:SS` (or `YYYY-MM-DDTHH:MM:SS`) UTC time into a UNIX timestamp.
+fn parse_date(date: &str) -> Option<u64> {
+    let (day, time) = date.trim().split_once([' ', 'T'])?;
+    let mut day = day.splitn(3, '-').map(str::parse::<i64>);
+    let (y, m, d) = (day.next()?.ok()?, day.next()?.ok()?, day.next()?.ok()?);
+    let mut time = time
+        .trim_end_matches('Z')
+        .splitn(3, ':')
+        .map(str::parse::<u64>);
+    let (hh, mm) = (time.next()?.ok()?, time.next()?.ok()?);
+    let ss = time.next().transpose().ok()?.unwrap_or(0);
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) || hh > 23 || mm > 59 || ss > 60 {
+        return None;
+    }
+    // days since the epoch from the civil date, as in http://howardhinnant.github.io/date_algorithms.html
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146097 + doe - 719468).ok()?;
+    Some(days * 24 * 60 * 60 + hh * 60 * 60 + mm * 60 + ss)
+}
+
+/// Load questions exported from elsewhere into an existing event.
+///
+/// The body is the export itself, in the given `format`. Imported questions keep their votes and
+/// whether they were answered or hidden, and don't need approval even if the event is moderated.
+/// Importing the same export into the same event again only adds the questions that aren't there
+/// yet, such as after an import that failed part-way.
+pub(super) async fn import(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    Query(query): Query<ImportQuery>,
+    State(dynamo): State<Backend>,
+    body: String,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let event = utils::check_secret(&dynamo, &eid, &secret).await?;
+
+    let qs = match parse(query.format, &body, dynamo.now()) {
+        Ok(qs) => qs,
+        Err(e) => {
+            warn!(%eid, format = ?query.format, error = %e, "ignoring invalid import");
+            return Err(http::StatusCode::BAD_REQUEST);
+        }
+    };
+    if qs.len() > MAX_QUESTIONS {
+        warn!(%eid, n = qs.len(), "ignoring overly large import");
+        return Err(http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    if qs.iter().any(|q| q.text.trim().is_empty()) {
+        warn!(%eid, "ignoring import with empty questions");
+        return Err(http::StatusCode::BAD_REQUEST);
+    }
+    // imported questions are held to the same bound as asked ones, even though the import as a
+    // whole may be much larger
+    if qs.iter().any(|q| ask::too_long(&q.text, q.who.as_deref())) {
+        warn!(%eid, "ignoring import with overly long questions");
+        return Err(http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    match load(&dynamo, &eid, &body, qs, event.settings.question_ttl()).await {
+        Ok(qids) => {
+            debug!(%eid, n = qids.len(), "imported questions");
+            Ok(Json(serde_json::json!({ "imported": qids.len() })))
+        }
+        Err(store::Error::NotFound) => {
+            warn!(%eid, "attempted to import into non-existing event");
+            Err(http::StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "request to import questions failed");
+            Err(http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// The ids of the `n` questions parsed from `input` when imported into `eid` at `now`.
+///
+/// Only the time part of each id comes from `now`. The rest comes from the event, the export, and
+/// where in the export the question is, so it's the same every time the export is imported.
+fn qids(eid: &Ulid, input: &str, n: usize, now: SystemTime) -> Vec<Ulid> {
+    let ms = Ulid::from_datetime(now).timestamp_ms();
+    let import = Sha256::new()
+        .chain_update(eid.to_bytes())
+        .chain_update(input.as_bytes());
+    (0..n as u64)
+        .map(|i| {
+            let digest = import.clone().chain_update(i.to_le_bytes()).finalize();
+            let random =
+                u128::from_be_bytes(digest[..16].try_into().expect("digest is long enough"));
+            Ulid::from_parts(ms, random)
+        })
+        .collect()
+}
+
+/// Write the questions parsed from `input` to an event, keeping each question for `ttl`, and
+/// return the ids of the ones that were written.
+///
+/// Questions that were already imported from the same `input` are skipped, so if a write fails
+/// part-way, importing the same export again adds just the questions that didn't make it. The
+/// rest go to the store in one [`import`](store::Store::import), which writes them in batches.
+/// This is also how the test event is seeded.
+pub(crate) async fn load(
+    dynamo: &Backend,
+    eid: &Ulid,
+    input: &str,
+    qs: Vec<Exported>,
+    ttl: Duration,
+) -> Result<Vec<Ulid>, store::Error> {
+    // ids differ between attempts only in their time part
+    let taken: HashSet<_> = dynamo
+        .list(eid, true)
+        .await?
+        .into_iter()
+        .map(|q| q.id.random())
+        .collect();
+    let time = |secs| {
+        utils::from_unix_timestamp(secs)
+            .ok_or_else(|| store::Error::Backend("imported time is out of range".into()))
+    };
+    let mut new = Vec::with_capacity(qs.len());
+    for (id, q) in qids(eid, input, qs.len(), dynamo.now()).into_iter().zip(qs) {
+        if taken.contains(&id.random()) {
+            continue;
+        }
+        new.push(ImportedQuestion {
+            id,
+            text: q.text,
+            who: q.who,
+            when: time(q.when)?,
+            votes: q.votes,
+            hidden: q.hidden,
+            answered: q.answered.map(time).transpose()?,
+        });
+    }
+    dynamo.import(eid, &new, ttl).await?;
+    let qids: Vec<_> = new.iter().map(|q| q.id).collect();
+    for &qid in &qids {
+        dynamo.publish(Update::Asked {
+            eid: *eid,
+            qid,
+            pending: false,
+        });
+    }
+    Ok(qids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Secret;
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("1970-01-01 00:00:00"), Some(0));
+        assert_eq!(parse_date("2022-11-03T01:12:41Z"), Some(1667437961));
+        assert_eq!(parse_date("2024-02-29 12:30"), Some(1709209800));
+        assert_eq!(parse_date("2024-13-01 00:00:00"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let import = async |format, body: &str| {
+            super::import(
+                Path(eid),
+                Secret(secret.to_string()),
+                Query(ImportQuery { format }),
+                State(backend.clone()),
+                body.to_string(),
+            )
+            .await
+            .map(|r| r.0["imported"].as_u64().unwrap())
+        };
+
+        let liveask = r#"[
+            {"id": 0, "likes": 3, "text": "hello world", "hidden": true, "answered": false, "createTimeUnix": 1667437961},
+            {"id": 1, "likes": 7, "text": "hello moon", "hidden": false, "answered": true, "createTimeUnix": 1667437962}
+        ]"#;
+        assert_eq!(import(Format::LiveAsk, liveask).await, Ok(2));
+        let slido = "Question,Author,Likes,Date,Status\n\
+                     hello sun,Anonymous,2,2022-11-03 01:12:41,archived\n\
+                     \"hello, stars\",person,0,,\n";
+        assert_eq!(import(Format::Slido, slido).await, Ok(2));
+        // retrying an import that went through doesn't add anything
+        assert_eq!(import(Format::LiveAsk, liveask).await, Ok(0));
+
+        let mut qs = crate::export::questions(&backend, &eid).await.unwrap();
+        qs.sort_by(|a, b| a.text.cmp(&b.text));
+        let texts: Vec<_> = qs.iter().map(|q| &*q.text).collect();
+        assert_eq!(
+            texts,
+            ["hello moon", "hello sun", "hello world", "hello, stars"]
+        );
+        assert_eq!((qs[0].votes, qs[0].hidden), (7, false));
+        assert!(qs[0].answered.is_some());
+        assert_eq!(qs[1].who, None);
+        assert_eq!(qs[1].when, 1667437961);
+        assert!(qs[1].answered.is_some());
+        assert_eq!((qs[2].votes, qs[2].hidden), (3, true));
+        assert_eq!(qs[2].when, 1667437961);
+        assert_eq!(qs[3].who.as_deref(), Some("person"));
+        assert_eq!(qs[3].answered, None);
+
+        // our own exports can be loaded into another event as-is
+        let e2 = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid2 = Ulid::from_string(e2["id"].as_str().unwrap()).unwrap();
+        for format in [Format::Json, Format::Csv] {
+            let body = match format {
+                Format::Json => serde_json::to_string(&qs).unwrap(),
+                _ => {
+                    let mut w = csv::Writer::from_writer(Vec::new());
+                    for q in &qs {
+                        w.serialize(q).unwrap();
+                    }
+                    String::from_utf8(w.into_inner().unwrap()).unwrap()
+                }
+            };
+            let n = super::import(
+                Path(eid2),
+                Secret(e2["secret"].as_str().unwrap().to_string()),
+                Query(ImportQuery { format }),
+                State(backend.clone()),
+                body,
+            )
+            .await
+            .unwrap();
+            assert_eq!(n.0["imported"], 4);
+        }
+        let mut qs2 = crate::export::questions(&backend, &eid2).await.unwrap();
+        qs2.sort_by(|a, b| a.text.cmp(&b.text));
+        let twice: Vec<_> = qs.iter().flat_map(|q| [q.clone(), q.clone()]).collect();
+        assert_eq!(qs2, twice);
+
+        // an import that failed after writing some of its questions can just be retried, here as
+        // if only the first question made it
+        let e3 = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid3 = Ulid::from_string(e3["id"].as_str().unwrap()).unwrap();
+        let ttl = *crate::QUESTIONS_TTL;
+        let parsed = parse(Format::LiveAsk, liveask, backend.now()).unwrap();
+        let first = load(&backend, &eid3, liveask, parsed[..1].to_vec(), ttl)
+            .await
+            .unwrap();
+        assert_eq!(first.len(), 1);
+        let rest = load(&backend, &eid3, liveask, parsed.clone(), ttl)
+            .await
+            .unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_ne!(first[0].random(), rest[0].random());
+        let again = load(&backend, &eid3, liveask, parsed, ttl).await.unwrap();
+        assert!(again.is_empty());
+        let mut qs3 = crate::export::questions(&backend, &eid3).await.unwrap();
+        qs3.sort_by(|a, b| a.text.cmp(&b.text));
+        let texts: Vec<_> = qs3.iter().map(|q| &*q.text).collect();
+        assert_eq!(texts, ["hello moon", "hello world"]);
+
+        // nonsense is refused, and so are strangers
+        assert_eq!(
+            import(Format::LiveAsk, "hello").await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            import(Format::Slido, "Author\nperson\n").await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        // as are questions that couldn't have been asked for being too long, and the questions
+        // that come with them
+        let long = "hello world ".repeat(ask::MAX_BODY / 12 + 1);
+        assert_eq!(
+            import(
+                Format::Slido,
+                &format!("Question,Author\nhello comet,\n{long},person\n")
+            )
+            .await,
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        let text = &long[..ask::MAX_BODY - "person".len()];
+        assert_eq!(
+            import(
+                Format::Slido,
+                &format!("Question,Author\n{text},person\nhello comet,\n{text}x,\n")
+            )
+            .await,
+            Ok(3)
+        );
+        let res = super::import(
+            Path(eid),
+            Secret("wrong".into()),
+            Query(ImportQuery {
+                format: Format::Json,
+            }),
+            State(backend.clone()),
+            "[]".into(),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        backend.delete(&eid).await;
+        backend.delete(&eid2).await;
+        backend.delete(&eid3).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
+}
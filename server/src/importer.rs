@@ -0,0 +1,37 @@
+//! Load questions exported from LiveAsk, Slido or another event into an existing event.
+//!
+//! ```sh
+//! import <event id> <liveask|slido|json|csv> [file]
+//! ```
+//!
+//! The export is read from standard input if no file is given. This writes to the store selected
+//! by the environment, the same way the server picks one (`USE_DYNAMODB`, `USE_SQLITE`, ...).
+
+use lambda_http::Error;
+use std::io::Read;
+use tracing_subscriber::EnvFilter;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    let (Some(eid), Some(format)) = (args.next(), args.next()) else {
+        eprintln!("usage: import <event id> <liveask|slido|json|csv> [file]");
+        std::process::exit(2);
+    };
+    let input = match args.next() {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+
+    let n = wewerewondering_api::import(&eid, &format, &input).await?;
+    println!("imported {n} questions into {eid}");
+    Ok(())
+}
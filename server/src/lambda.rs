@@ -17,7 +17,7 @@ async fn main() -> Result<(), Error> {
     let app = wewerewondering_api::new().await;
     // To run with AWS Lambda runtime, wrap in our `LambdaLayer`
     let app = tower::ServiceBuilder::new()
-        .layer(TraceLayer::new_for_http())
+        .layer(TraceLayer::new_for_http().make_span_with(wewerewondering_api::request_span))
         .layer(LambdaLayer)
         .service(app);
 
@@ -1,18 +1,30 @@
 use aws_sdk_dynamodb::config::Credentials;
-use aws_sdk_dynamodb::types::AttributeValue;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post};
 use axum::Router;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::ops::Deref;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, SystemTime};
+use tower::Layer;
 use tower_http::limit::RequestBodyLimitLayer;
-use ulid::Ulid;
 
+mod admin;
 mod ask;
+mod clock;
+mod credentials;
 mod event;
+mod export;
+mod hub;
+mod import;
 mod list;
+mod moderate;
 mod new;
 mod questions;
+mod settings;
+mod sign;
+mod socket;
+mod store;
+mod stream;
+mod ticket;
 mod toggle;
 mod utils;
 mod vote;
@@ -20,23 +32,52 @@ mod vote;
 #[cfg(debug_assertions)]
 const SEED: &str = include_str!("test.json");
 
-const QUESTIONS_EXPIRE_AFTER_DAYS: u64 = 30;
-const QUESTIONS_TTL: Duration = Duration::from_secs(QUESTIONS_EXPIRE_AFTER_DAYS * 24 * 60 * 60);
-
-const EVENTS_EXPIRE_AFTER_DAYS: u64 = 60;
-const EVENTS_TTL: Duration = Duration::from_secs(EVENTS_EXPIRE_AFTER_DAYS * 24 * 60 * 60);
+fn days(n: u64) -> Duration {
+    Duration::from_secs(n.saturating_mul(24 * 60 * 60))
+}
 
-#[derive(Clone, Debug, Default)]
-pub(crate) struct Local {
-    pub events: HashMap<Ulid, String>,
-    pub questions: HashMap<Ulid, HashMap<&'static str, AttributeValue>>,
-    pub questions_by_eid: HashMap<Ulid, Vec<Ulid>>,
+fn days_from_env(var: &str, default: u64) -> Duration {
+    days(
+        std::env::var(var)
+            .map(|val| val.parse::<u64>().ok())
+            .ok()
+            .flatten()
+            .unwrap_or(default),
+    )
 }
 
+// How long questions are kept after they're asked, unless the host asks for longer.
+static QUESTIONS_TTL: LazyLock<Duration> =
+    LazyLock::new(|| days_from_env("QUESTIONS_EXPIRE_AFTER_DAYS", 30));
+
+// How long events are kept after they close (or are made), unless the host asks for longer.
+static EVENTS_TTL: LazyLock<Duration> =
+    LazyLock::new(|| days_from_env("EVENTS_EXPIRE_AFTER_DAYS", 60));
+
+// The longest a host can ask for questions and events to be kept.
+static MAX_TTL: LazyLock<Duration> = LazyLock::new(|| days_from_env("MAX_EXPIRE_AFTER_DAYS", 365));
+
+/// A handle to whichever [`Store`](store::Store) the API is running against.
+///
+/// This is the state shared by all the handlers, so it also holds the [`Clock`](clock::Clock)
+/// that they (and the store) use to tell the time, the [`Hub`](hub::Hub) that they publish
+/// changes to, the [`Tickets`](ticket::Tickets) for opening live views, and the
+/// [`Key`](sign::Key) that they sign what they hand out to clients with.
 #[derive(Clone, Debug)]
-pub(crate) enum Backend {
-    Dynamo(aws_sdk_dynamodb::Client),
-    Local(Arc<Mutex<Local>>),
+pub(crate) struct Backend {
+    store: Arc<dyn store::Store>,
+    clock: clock::Clock,
+    hub: hub::Hub,
+    tickets: ticket::Tickets,
+    key: sign::Key,
+}
+
+impl Deref for Backend {
+    type Target = dyn store::Store;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.store
+    }
 }
 
 /// Instantiate a DynamoDB client.
@@ -105,37 +146,197 @@ pub async fn init_dynamodb_client() -> aws_sdk_dynamodb::Client {
 }
 
 impl Backend {
-    #[allow(unused)]
+    fn with_store(store: impl store::Store + 'static, clock: clock::Clock) -> Self {
+        Backend {
+            store: Arc::new(store),
+            clock,
+            hub: hub::Hub::default(),
+            tickets: ticket::Tickets::default(),
+            key: sign::Key::from_env(),
+        }
+    }
+
+    /// The current time, according to the backend's clock.
+    pub(crate) fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    /// Let live subscribers to the affected event know about a change.
+    pub(crate) fn publish(&self, update: hub::Update) {
+        self.hub.publish(update);
+    }
+
+    pub(crate) fn subscribe(&self) -> tokio::sync::broadcast::Receiver<hub::Update> {
+        self.hub.subscribe()
+    }
+
+    pub(crate) fn issue_ticket(&self, eid: ulid::Ulid, secret: String) -> String {
+        self.tickets.issue(eid, secret, self.now())
+    }
+
+    pub(crate) fn redeem_ticket(&self, eid: &ulid::Ulid, ticket: &str) -> Option<String> {
+        self.tickets.redeem(eid, ticket, self.now())
+    }
+
+    /// Sign `parts` with the server's key, see [`sign::Key::sign`].
+    pub(crate) fn sign(&self, parts: &[&str]) -> String {
+        self.key.sign(parts)
+    }
+
+    pub(crate) fn verify(&self, parts: &[&str], signature: &str) -> bool {
+        self.key.verify(parts, signature)
+    }
+
     async fn local() -> Self {
-        Backend::Local(Arc::new(Mutex::new(Local::default())))
+        Backend::local_with_clock(clock::Clock::default())
+    }
+
+    /// Instantiate an in-memory backend whose notion of time is given by `clock`.
+    fn local_with_clock(clock: clock::Clock) -> Self {
+        Backend::with_store(store::Local::with_clock(clock.clone()), clock)
+    }
+
+    /// Instantiate an in-memory backend that is persisted to the journal at `path`.
+    ///
+    /// See [`store::Local::open`].
+    fn local_persisted(path: impl AsRef<std::path::Path>) -> Self {
+        let clock = clock::Clock::default();
+        let store = store::Local::open(path, clock.clone()).expect("failed to load local journal");
+        Backend::with_store(store, clock)
     }
 
     /// Instantiate a DynamoDB backend.
     ///  
     /// This will instantiate a client (see [`init_dynamodb_client`])
-    /// and put it inside [`Backend::Dynamo`].
+    /// and put it inside a [`store::Dynamo`].
     pub async fn dynamo() -> Self {
-        Backend::Dynamo(init_dynamodb_client().await)
+        let clock = clock::Clock::default();
+        let store = store::Dynamo::new(init_dynamodb_client().await, clock.clone());
+        Backend::with_store(store, clock)
+    }
+
+    /// Instantiate a SQLite backend.
+    ///
+    /// The database at `path` is created (and migrated) if needed. Pass `:memory:` for a
+    /// database that only lives as long as the process.
+    #[cfg(feature = "sqlite")]
+    pub async fn sqlite(path: impl AsRef<std::path::Path>) -> Self {
+        let clock = clock::Clock::default();
+        let store = store::Sqlite::open(path, clock.clone())
+            .await
+            .expect("failed to open SQLite database");
+        Backend::with_store(store, clock)
+    }
+
+    /// Instantiate a PostgreSQL backend.
+    ///
+    /// The connection URL is taken from `USE_POSTGRES`, and falls back to
+    /// "postgres://localhost/wewerewondering". Any pending schema migrations are applied before
+    /// this returns, so you can run the entire test suite against a fresh local database with:
+    /// ```sh
+    /// USE_POSTGRES=postgres://localhost/wewerewondering cargo t --features postgres -- --include-ignored
+    /// ```
+    #[cfg(feature = "postgres")]
+    pub async fn postgres() -> Self {
+        let url = std::env::var("USE_POSTGRES")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .unwrap_or("postgres://localhost/wewerewondering".into());
+        let clock = clock::Clock::default();
+        let store = store::Postgres::connect(&url, clock.clone())
+            .await
+            .expect("failed to connect to PostgreSQL");
+        Backend::with_store(store, clock)
+    }
+
+    /// Instantiate the backend selected by the environment.
+    ///
+    /// `USE_DYNAMODB` selects DynamoDB (see [`init_dynamodb_client`]), `USE_POSTGRES` selects
+    /// PostgreSQL (see [`Backend::postgres`]), and `USE_SQLITE=<path>` selects the SQLite
+    /// database at `<path>` (see [`Backend::sqlite`]). `USE_LOCAL=<path>` selects the in-memory
+    /// [`store::Local`], persisted to `<path>`, which makes for a standalone server that needs no
+    /// database at all. With none of them set, release builds use DynamoDB and debug builds use
+    /// a non-persisted [`store::Local`].
+    async fn from_env() -> Self {
+        if std::env::var_os("USE_DYNAMODB").is_some() {
+            return Backend::dynamo().await;
+        }
+        if std::env::var_os("USE_POSTGRES").is_some() {
+            #[cfg(feature = "postgres")]
+            return Backend::postgres().await;
+            #[cfg(not(feature = "postgres"))]
+            panic!("USE_POSTGRES requires building with `--features postgres`");
+        }
+        if let Some(path) = std::env::var_os("USE_SQLITE") {
+            #[cfg(feature = "sqlite")]
+            return Backend::sqlite(path).await;
+            #[cfg(not(feature = "sqlite"))]
+            panic!("USE_SQLITE={path:?} requires building with `--features sqlite`");
+        }
+        if let Some(path) = std::env::var_os("USE_LOCAL") {
+            return Backend::local_persisted(path);
+        }
+        if cfg!(debug_assertions) {
+            Backend::local().await
+        } else {
+            Backend::dynamo().await
+        }
     }
 }
 
+/// Import questions into an existing event, in the store selected by the environment.
+///
+/// This is what the `import` binary does, and takes the same formats as the import endpoint.
+/// Returns how many questions were imported.
+pub async fn import(
+    eid: &str,
+    format: &str,
+    input: &str,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let eid: ulid::Ulid = eid.parse()?;
+    let format: import::Format = format.parse()?;
+    let backend = Backend::from_env().await;
+    let Some(event) = backend.event(&eid).await? else {
+        return Err(format!("event {eid} does not exist").into());
+    };
+    let qs = import::parse(format, input, backend.now())?;
+    let qids = import::load(&backend, &eid, input, qs, event.settings.question_ttl()).await?;
+    Ok(qids.len())
+}
+
+/// Run an administrative command against the store selected by the environment.
+///
+/// This is what the `admin` binary does, with `args` being its command-line arguments. Output goes
+/// to standard output.
+pub async fn admin(args: &[String]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cmd: admin::Command = args.try_into()?;
+    let backend = Backend::from_env().await;
+    admin::run(&backend, cmd, &mut std::io::stdout().lock()).await
+}
+
+/// The API, as served from Lambda.
 pub async fn new() -> Router {
-    #[cfg(not(debug_assertions))]
-    let backend = Backend::dynamo().await;
+    app(false).await
+}
+
+/// The API, as served by a long-running server process.
+///
+/// This has all the routes of [`new`], plus the live question stream and socket, which only work
+/// when all clients are served by the same process.
+pub async fn standalone() -> Router {
+    app(true).await
+}
+
+async fn app(live: bool) -> Router {
+    let backend = Backend::from_env().await;
 
     #[cfg(debug_assertions)]
-    let backend = {
+    {
         use rand::prelude::IndexedRandom;
 
-        let mut backend = if std::env::var_os("USE_DYNAMODB").is_some() {
-            Backend::dynamo().await
-        } else {
-            Backend::local().await
-        };
-
         // to aid in development, seed the backend with a test event and related
         // questions, and auto-generate user votes over time
-        let qids = crate::utils::seed(&mut backend).await;
+        let (eid, qids) = crate::utils::seed(&backend).await;
         let cheat = backend.clone();
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(1));
@@ -145,25 +346,204 @@ pub async fn new() -> Router {
                 let qid = qids
                     .choose(&mut rand::rng())
                     .expect("there _are_ some questions for our test event");
-                let _ = cheat.vote(qid, vote::UpDown::Up).await;
+                // every vote from a different "user", so that none of them are rejected
+                let voter = ulid::Ulid::generate();
+                if let Ok(votes) = cheat.vote(&eid, qid, &voter, vote::UpDown::Up).await {
+                    cheat.publish(hub::Update::Voted { qid: *qid, votes });
+                }
             }
         });
+    }
 
-        backend
-    };
+    router(backend, live)
+}
 
-    Router::new()
+fn router(backend: Backend, live: bool) -> Router {
+    let router = Router::new()
         .route("/api/event", post(new::new))
         .route("/api/event/{eid}", post(ask::ask))
         .route("/api/event/{eid}", get(event::event))
+        // host routes take the secret in the `Authorization` header, see `unpath_secret` for the
+        // old ones that had it in the path
         .route("/api/event/{eid}/questions", get(list::list))
-        .route("/api/event/{eid}/questions/{secret}", get(list::list_all))
+        .route("/api/event/{eid}/present", get(list::present))
         .route(
-            "/api/event/{eid}/questions/{secret}/{qid}/toggle/{property}",
+            "/api/event/{eid}/questions/{qid}/toggle/{property}",
             post(toggle::toggle),
         )
-        .route("/api/vote/{qid}/{updown}", post(vote::vote))
-        .route("/api/questions/{qids}", get(questions::questions))
-        .layer(RequestBodyLimitLayer::new(1024))
-        .with_state(backend)
+        .route("/api/event/{eid}/moderate", post(moderate::moderate))
+        .route("/api/event/{eid}/export", get(export::export))
+        .route("/api/event/{eid}/secret", post(credentials::rotate))
+        .route(
+            "/api/event/{eid}/tokens",
+            get(credentials::tokens).post(credentials::mint),
+        )
+        .route(
+            "/api/event/{eid}/tokens/{name}",
+            delete(credentials::revoke),
+        )
+        .route(
+            "/api/event/{eid}/settings",
+            get(settings::settings).patch(settings::update),
+        )
+        .route("/api/event/{eid}/vote/{qid}/{updown}", post(vote::vote))
+        .route("/api/vote/{qid}/{updown}", post(vote::vote_question))
+        .route("/api/questions/{qids}", get(questions::questions));
+    let router = if live {
+        router
+            .route("/api/event/{eid}/stream", get(stream::stream))
+            .route("/api/event/{eid}/socket", get(socket::socket))
+            .route("/api/event/{eid}/ticket", post(ticket::ticket))
+    } else {
+        router
+    };
+    let router = router
+        .layer(RequestBodyLimitLayer::new(ask::MAX_BODY))
+        // imports are the one place where more than a question's worth is uploaded
+        .route(
+            "/api/event/{eid}/import",
+            post(import::import).layer(RequestBodyLimitLayer::new(import::MAX_BODY)),
+        )
+        .with_state(backend);
+    // the rewrite has to happen before routing, so it wraps the whole router
+    Router::new().fallback_service(axum::middleware::map_request(unpath_secret).layer(router))
+}
+
+/// Turn a request to an old-style host route, with the secret in its path, into one to the
+/// equivalent route that takes the secret in the `Authorization` header.
+///
+/// Secrets in paths end up in access logs along the way, so the path form is deprecated. An
+/// `Authorization` header that is already there wins over the path.
+async fn unpath_secret(mut req: axum::extract::Request) -> axum::extract::Request {
+    let path = req.uri().path();
+    let Some(range) = utils::secret_in_path(req.method(), path) else {
+        return req;
+    };
+    let secret = path[range.start + 1..range.end].to_string();
+    let mut uri = format!("{}{}", &path[..range.start], &path[range.end..]);
+    if let Some(query) = req.uri().query() {
+        uri.push('?');
+        uri.push_str(query);
+    }
+    tracing::debug!(%uri, "request to deprecated route with secret in path");
+    *req.uri_mut() = uri
+        .parse()
+        .expect("removing a path segment keeps the URI valid");
+    if !req.headers().contains_key(http::header::AUTHORIZATION) {
+        if let Ok(value) = http::HeaderValue::from_str(&format!("Bearer {secret}")) {
+            req.headers_mut().insert(http::header::AUTHORIZATION, value);
+        }
+    }
+    req
+}
+
+/// The tracing span for a request, like the one [`TraceLayer`](tower_http::trace::TraceLayer)
+/// makes by default, but without any host secret that is in the path.
+pub fn request_span<B>(req: &http::Request<B>) -> tracing::Span {
+    let mut uri = req.uri().path().to_string();
+    if let Some(range) = utils::secret_in_path(req.method(), &uri) {
+        uri.replace_range(range, "/{secret}");
+    }
+    if let Some(query) = req.uri().query() {
+        uri.push('?');
+        uri.push_str(query);
+    }
+    tracing::debug_span!(
+        "request",
+        method = %req.method(),
+        uri,
+        version = ?req.version(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use http::{header, Method, Request, StatusCode};
+    use tower::Service;
+
+    #[test]
+    fn secret_in_path() {
+        let redact = |method: &Method, path: &str| {
+            let mut path = path.to_string();
+            if let Some(range) = utils::secret_in_path(method, &path) {
+                path.replace_range(range, "");
+            }
+            path
+        };
+        let get = &Method::GET;
+        for (old, new) in [
+            ("/api/event/E/questions/S", "/api/event/E/questions"),
+            (
+                "/api/event/E/questions/S/Q/toggle/hidden",
+                "/api/event/E/questions/Q/toggle/hidden",
+            ),
+            ("/api/event/E/settings/S", "/api/event/E/settings"),
+            ("/api/event/E/tokens/S", "/api/event/E/tokens"),
+            ("/api/event/E/tokens/S/name", "/api/event/E/tokens/name"),
+            ("/api/event/E/stream/S", "/api/event/E/stream"),
+        ] {
+            assert_eq!(redact(get, old), new);
+        }
+        // and new-style routes are left alone
+        for path in [
+            "/api/event/E",
+            "/api/event/E/questions",
+            "/api/event/E/questions/Q/toggle/hidden",
+            "/api/event/E/settings",
+            "/api/vote/Q/up",
+            "/api/event/E/vote/Q/up",
+        ] {
+            assert_eq!(redact(get, path), path);
+        }
+        assert_eq!(
+            redact(&Method::DELETE, "/api/event/E/tokens/name"),
+            "/api/event/E/tokens/name"
+        );
+    }
+
+    #[tokio::test]
+    async fn secret_in_header() {
+        let mut app = router(Backend::local().await, false);
+        let mut send = async |method: Method, uri: &str, secret: Option<&str>| {
+            let mut req = Request::builder().method(method).uri(uri);
+            if let Some(secret) = secret {
+                req = req.header(header::AUTHORIZATION, format!("Bearer {secret}"));
+            }
+            app.call(req.body(Body::empty()).unwrap()).await.unwrap()
+        };
+
+        let res = send(Method::POST, "/api/event", None).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let e: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let eid = e["id"].as_str().unwrap();
+        let secret = e["secret"].as_str().unwrap();
+
+        let settings = format!("/api/event/{eid}/settings");
+        let res = send(Method::GET, &settings, Some(secret)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = send(Method::GET, &settings, Some("wrong")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = send(Method::GET, &settings, None).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // the old form still works
+        let res = send(Method::GET, &format!("{settings}/{secret}"), None).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = send(Method::GET, &format!("{settings}/wrong"), None).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // and the same list is for guests or hosts, depending on the header
+        let questions = format!("/api/event/{eid}/questions");
+        let res = send(Method::GET, &questions, None).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "max-age=10");
+        let res = send(Method::GET, &questions, Some(secret)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "max-age=3");
+    }
 }
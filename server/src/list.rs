@@ -1,24 +1,19 @@
-use super::{Backend, Local};
-use crate::utils;
-use aws_sdk_dynamodb::{
-    error::SdkError,
-    operation::query::{QueryError, QueryOutput},
-    types::{error::ResourceNotFoundException, AttributeValue},
-};
+use super::Backend;
+use crate::settings::Phase;
+use crate::store::{self, ListedQuestion};
+use crate::utils::{self, Secret};
 use axum::response::Json;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::AppendHeaders,
 };
 use http::{
     header::{self, HeaderName},
-    StatusCode,
-};
-use std::{
-    collections::HashMap,
-    sync::LazyLock,
-    time::{Duration, SystemTime},
+    HeaderMap, HeaderValue, StatusCode,
 };
+use serde::Deserialize;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::{sync::LazyLock, time::Duration};
 use ulid::Ulid;
 
 #[allow(unused_imports)]
@@ -42,289 +37,380 @@ static GUEST_CACHE_CONTROL: LazyLock<&str> = LazyLock::new(|| {
         .unwrap_or(10);
     format!("max-age={}", max_age).leak()
 });
+static LOCKED_CACHE_CONTROL: LazyLock<&str> = LazyLock::new(|| {
+    let max_age = std::env::var("LOCKED_CACHE_CONTROL_MAX_AGE")
+        .map(|val| val.parse::<usize>().ok())
+        .ok()
+        .flatten()
+        // votes no longer move once an event is locked, only the host's toggles do
+        .unwrap_or(300);
+    format!("max-age={}", max_age).leak()
+});
 
 // It's enough for the most-popular questions to get above the fold to answer.
 const TOP_N: usize = 5;
 
-impl Backend {
-    pub(super) async fn list(
-        &self,
-        eid: &Ulid,
-        has_secret: bool,
-    ) -> Result<QueryOutput, SdkError<QueryError>> {
-        match self {
-            Self::Dynamo(dynamo) => {
-                let query = dynamo.query();
-                let query = query
-                    .table_name("questions")
-                    .index_name("top")
-                    .scan_index_forward(false)
-                    .key_condition_expression("eid = :eid")
-                    .expression_attribute_values(":eid", AttributeValue::S(eid.to_string()));
-
-                let query = if has_secret {
-                    query
-                } else {
-                    query
-                        .filter_expression("#hidden = :false")
-                        .expression_attribute_names("#hidden", "hidden".to_string())
-                        .expression_attribute_values(":false", AttributeValue::Bool(false))
-                };
+// Changes may still be on their way to the store when a change token is handed out, so tokens
+// point a little into the past. Clients just get the most recent changes twice.
+const SETTLE: Duration = Duration::from_secs(5);
 
-                query.send().await
-            }
-            Self::Local(local) => {
-                let mut local = local.lock().unwrap();
-                let Local {
-                    questions,
-                    questions_by_eid,
-                    events,
-                    ..
-                } = &mut *local;
-
-                if !events.contains_key(eid) {
-                    return Err(utils::mint_service_error(
-                        QueryError::ResourceNotFoundException(
-                            ResourceNotFoundException::builder().build(),
-                        ),
-                    ));
-                }
+// How old a change token guests may use. Older ones could be used to dig up questions that were
+// hidden long ago, so those clients have to start over from a full list. Tokens are signed, so
+// that guests can't make up ones from before questions they never saw were hidden either.
+const MAX_TOKEN_AGE: Duration = Duration::from_secs(60 * 60);
 
-                let qs = questions_by_eid
-                    .get_mut(eid)
-                    .expect("list for non-existing event");
-
-                Ok(QueryOutput::builder()
-                    .set_count(Some(qs.len() as i32))
-                    .set_items(Some(
-                        qs.iter()
-                            .filter_map(|qid| {
-                                let q = &questions[qid];
-                                if has_secret {
-                                    Some(
-                                        q.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
-                                    )
-                                } else if q["hidden"] == AttributeValue::Bool(false) {
-                                    Some(
-                                        q.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
-                                    )
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect(),
-                    ))
-                    .build())
-            }
-        }
+type Headers = AppendHeaders<Vec<(HeaderName, HeaderValue)>>;
+
+fn cache_control(value: &'static str) -> Headers {
+    AppendHeaders(vec![(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(value),
+    )])
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub(super) struct ListQuery {
+    /// The change token from an earlier list, to only get the questions that changed since.
+    ///
+    /// `0` gives the whole list along with a first token. Guests have to start over like that once
+    /// their token is more than an hour old.
+    since: Option<String>,
+}
+
+/// Hand out a change token for the changes to `eid` from `issued` (in seconds since the UNIX
+/// epoch) onwards.
+fn change_token(dynamo: &Backend, eid: &Ulid, issued: u64) -> String {
+    let issued = issued.to_string();
+    let signature = dynamo.sign(&["since", &eid.to_string(), &issued]);
+    format!("{issued}.{signature}")
+}
+
+/// When the change token `token` for `eid` was handed out, as long as we're the ones that did.
+fn token_issued(dynamo: &Backend, eid: &Ulid, token: &str) -> Option<u64> {
+    if token == "0" {
+        return Some(0);
+    }
+    let (issued, signature) = token.split_once('.')?;
+    if !dynamo.verify(&["since", &eid.to_string(), issued], signature) {
+        return None;
     }
+    issued.parse().ok()
 }
 
+/// Who is asking for the list, and with what credential.
+enum Access {
+    Guest,
+    Host(String),
+    Presenter(String),
+}
+
+/// List an event's questions, including hidden and pending ones if a host secret is given.
 pub(super) async fn list(
     Path(eid): Path<Ulid>,
+    secret: Option<Secret>,
+    Query(query): Query<ListQuery>,
+    headers: HeaderMap,
     State(dynamo): State<Backend>,
-) -> (
-    AppendHeaders<[(HeaderName, &'static str); 1]>,
-    Result<Json<serde_json::Value>, StatusCode>,
-) {
-    list_inner(Path((eid, None)), State(dynamo)).await
+) -> (Headers, Result<Json<serde_json::Value>, StatusCode>) {
+    let access = match secret {
+        Some(Secret(secret)) => Access::Host(secret),
+        None => Access::Guest,
+    };
+    list_inner(eid, access, query, &headers, dynamo).await
 }
 
-pub(super) async fn list_all(
-    Path((eid, secret)): Path<(Ulid, String)>,
+/// The host's list, for putting up on stage.
+///
+/// This leaves out questions that are waiting for approval, and hidden questions unless the
+/// presenter token says otherwise. Any host credential works here too.
+pub(super) async fn present(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    Query(query): Query<ListQuery>,
+    headers: HeaderMap,
     State(dynamo): State<Backend>,
-) -> (
-    AppendHeaders<[(HeaderName, &'static str); 1]>,
-    Result<Json<serde_json::Value>, StatusCode>,
-) {
-    list_inner(Path((eid, Some(secret))), State(dynamo)).await
+) -> (Headers, Result<Json<serde_json::Value>, StatusCode>) {
+    list_inner(eid, Access::Presenter(secret), query, &headers, dynamo).await
 }
 
 async fn list_inner(
-    Path((eid, secret)): Path<(Ulid, Option<String>)>,
-    State(dynamo): State<Backend>,
-) -> (
-    AppendHeaders<[(HeaderName, &'static str); 1]>,
-    Result<Json<serde_json::Value>, StatusCode>,
-) {
-    let has_secret = if let Some(secret) = secret {
-        debug!("list questions with admin access");
-        if let Err(e) = utils::check_secret(&dynamo, &eid, &secret).await {
-            // a bad secret will not turn good and
-            // events are unlikely to re-appear with the same Ulid
-            return (
-                AppendHeaders([(header::CACHE_CONTROL, "max-age=86400")]),
-                Err(e),
-            );
+    eid: Ulid,
+    access: Access,
+    query: ListQuery,
+    headers: &HeaderMap,
+    dynamo: Backend,
+) -> (Headers, Result<Json<serde_json::Value>, StatusCode>) {
+    let guest = matches!(access, Access::Guest);
+    // `has_secret` is whether the caller sees everything a host does, while `sees_hidden` is just
+    // about hidden questions, which presenters may be allowed to see as well
+    let (has_secret, sees_hidden, event) = match access {
+        Access::Host(secret) => {
+            debug!("list questions with admin access");
+            match utils::check_secret(&dynamo, &eid, &secret).await {
+                Ok(event) => (true, true, event),
+                // a bad secret will not turn good and
+                // events are unlikely to re-appear with the same Ulid
+                Err(e) => return (cache_control("max-age=86400"), Err(e)),
+            }
         }
-        true
-    } else {
-        trace!("list questions with guest access");
-        // ensure that the event exists:
-        // this is _just_ so give 404s for old events so clients stop polling
-        if let Err(e) = utils::get_secret(&dynamo, &eid).await {
-            // events are unlikely to re-appear with the same Ulid
+        Access::Presenter(secret) => {
+            debug!("list questions with presenter access");
+            match utils::check_presenter(&dynamo, &eid, &secret).await {
+                Ok((event, show_hidden)) => (false, show_hidden, event),
+                Err(e) => return (cache_control("max-age=86400"), Err(e)),
+            }
+        }
+        Access::Guest => {
+            trace!("list questions with guest access");
+            // ensure that the event exists:
+            // this is _just_ so give 404s for old events so clients stop polling
+            match utils::get_event(&dynamo, &eid).await {
+                Ok(event) => (false, false, event),
+                // events are unlikely to re-appear with the same Ulid
+                Err(e) => return (cache_control("max-age=86400"), Err(e)),
+            }
+        }
+    };
+    let since = match query
+        .since
+        .as_deref()
+        .map(|t| token_issued(&dynamo, &eid, t))
+    {
+        None => None,
+        Some(Some(since)) => Some(since),
+        Some(None) => {
+            warn!(%eid, "refusing forged change token");
             return (
-                AppendHeaders([(header::CACHE_CONTROL, "max-age=86400")]),
-                Err(e),
+                self::cache_control("no-cache"),
+                Err(http::StatusCode::BAD_REQUEST),
             );
         }
-        false
+    };
+    let cache_control = if !guest {
+        *HOST_CACHE_CONTROL
+    } else if event.settings.phase(dynamo.now()) == Phase::Locked {
+        *LOCKED_CACHE_CONTROL
+    } else {
+        *GUEST_CACHE_CONTROL
     };
 
-    // Closure moved out of the filter_map due to rustfmt failing to format the
-    // code properly.
-    let serialize_question = |doc: &HashMap<String, AttributeValue>| {
-        let qid = doc["id"].as_s().ok();
-        let votes = doc["votes"]
-            .as_n()
-            .ok()
-            .and_then(|v| v.parse::<usize>().ok());
-        let hidden = doc["hidden"].as_bool().ok();
-        let answered = doc
-            .get("answered")
-            .and_then(|v| v.as_n().ok())
-            .and_then(|v| v.parse::<usize>().ok());
-        match (qid, votes, hidden, answered) {
-            (Some(qid), Some(votes), Some(hidden), answered) => {
-                let mut v = serde_json::json!({
-                    "qid": qid,
-                    "votes": votes,
-                    "hidden": hidden,
-                });
-                if let Some(answered) = answered {
-                    v["answered"] = answered.into();
-                }
-                Some(v)
-            }
-            (Some(qid), _, _, _) => {
-                error!(%eid, %qid, votes = ?doc.get("votes"), "found non-numeric vote count");
-                None
-            }
-            _ => {
-                error!(%eid, ?doc, "found non-string question id");
-                None
-            }
+    let serialize_question = |q: ListedQuestion| {
+        let mut v = serde_json::json!({
+            "qid": q.id.to_string(),
+            "votes": q.votes,
+            "hidden": q.hidden,
+            "pending": q.pending,
+        });
+        if let Some(answered) = q.answered {
+            v["answered"] = utils::to_unix_timestamp(answered).into();
         }
+        v
     };
 
-    match dynamo.list(&eid, has_secret).await {
-        Ok(qs) => {
-            trace!(%eid, n = %qs.count(), "listed questions");
-            let questions: Vec<_> = qs.items().iter().filter_map(serialize_question).collect();
-
-            // sort based on "hotness" of the question over time:
-            // https://www.evanmiller.org/ranking-news-items-with-upvotes.html
-            // the wrapper struct is needed because f64 doesn't impl Ord
-            #[derive(Debug)]
-            #[repr(transparent)]
-            struct Score(f64);
-            impl PartialEq for Score {
-                fn eq(&self, other: &Self) -> bool {
-                    self.cmp(other).is_eq()
-                }
-            }
-            impl Eq for Score {}
-            impl PartialOrd for Score {
-                fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-                    Some(self.cmp(other))
-                }
-            }
-            impl Ord for Score {
-                fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-                    self.0.total_cmp(&other.0)
-                }
-            }
-            let now = SystemTime::now();
-            let score = |q: &serde_json::Value| {
-                let dt_in_minutes_rounded_down = now
-                    .duration_since(
-                        q["qid"]
-                            .as_str()
-                            .expect("it's a ULID")
-                            .parse::<Ulid>()
-                            .expect("produced as ULID by us")
-                            .datetime(),
-                    )
-                    .unwrap_or(Duration::ZERO)
-                    .as_secs()
-                    // in minutes so questions don't jump around quite as much
-                    / 60;
-                // +1 so that first minute questions don't get inf scores (for the ln)
-                let dt = dt_in_minutes_rounded_down + 1;
-                // +1 again to avoid NaN scores for first-minute questions (for / (1 - e^0)).
-                let dt = dt + 1;
-                // ln so that stories get less penalized for age over time
-                // after all, this is Q&A, not minute-to-minute hot news
-                let dt = (dt as f64).ln();
-                let votes = q["votes"].as_u64().expect("votes is a number") as f64;
-                // max so that even if vote count somehow got to 0, count it as 1
-                let votes = votes.max(1.);
-                let exp = (-1. * dt).exp_m1() + 1.;
-                Score(exp * votes / (1. - exp))
-            };
-
-            let (mut questions, mut answered_hidden): (Vec<_>, Vec<_>) =
-                questions.into_iter().partition(|item| {
-                    item.get("answered").is_none()
-                        && !item.get("hidden").eq(&Some(&serde_json::Value::Bool(true)))
-                });
-            questions.sort_by_key(|item| {
-                std::cmp::Reverse(item["votes"].as_u64().expect("votes is a number"))
-            });
-            if let Some(subslice) = questions.get_mut(TOP_N..) {
-                subslice.sort_by_cached_key(|q| std::cmp::Reverse(score(q)));
-            }
-            questions.append(&mut answered_hidden);
-            let cache_control = if has_secret {
-                *HOST_CACHE_CONTROL
-            } else {
-                *GUEST_CACHE_CONTROL
-            };
-            (
-                AppendHeaders([(header::CACHE_CONTROL, cache_control)]),
-                Ok(Json(serde_json::Value::from(questions))),
-            )
+    // guests also need to hear about questions that were hidden since they last looked
+    let qs = match dynamo
+        .list(&eid, has_secret || sees_hidden || since.is_some())
+        .await
+    {
+        Ok(qs) => qs,
+        Err(store::Error::NotFound) => {
+            warn!(%eid, "request for non-existing event");
+            // it's relatively unlikely that an event Ulid that didn't exist will start
+            // existing. but just in case, don't make it _too_ long.
+            return (
+                self::cache_control("max-age=3600"),
+                Err(http::StatusCode::NOT_FOUND),
+            );
         }
         Err(e) => {
-            if let SdkError::ServiceError(ref err) = e {
-                if err.err().is_resource_not_found_exception() {
-                    warn!(%eid, error = %e, "request for non-existing event");
-                    return (
-                        // it's relatively unlikely that an event Ulid that didn't exist will start
-                        // existing. but just in case, don't make it _too_ long.
-                        AppendHeaders([(header::CACHE_CONTROL, "max-age=3600")]),
-                        Err(http::StatusCode::NOT_FOUND),
-                    );
-                }
-            }
-            error!(%eid, error = %e, "dynamodb request for question list failed");
-            (
-                AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+            error!(%eid, error = %e, "request for question list failed");
+            return (
+                self::cache_control("no-cache"),
                 Err(http::StatusCode::INTERNAL_SERVER_ERROR),
-            )
+            );
+        }
+    };
+    trace!(%eid, n = %qs.len(), "listed questions");
+
+    if let Some(since) = since {
+        let now = utils::to_unix_timestamp(dynamo.now());
+        if !sees_hidden && since != 0 && since < now.saturating_sub(MAX_TOKEN_AGE.as_secs()) {
+            warn!(%eid, since, "refusing stale change token");
+            return (
+                self::cache_control("no-cache"),
+                Err(http::StatusCode::BAD_REQUEST),
+            );
+        }
+        let token = change_token(&dynamo, &eid, now.saturating_sub(SETTLE.as_secs()));
+        let questions: Vec<_> = qs
+            .into_iter()
+            .filter(|q| utils::to_unix_timestamp(q.modified) >= since)
+            // guests shouldn't even learn that pending questions exist
+            .filter(|q| has_secret || !q.pending)
+            // nor about hidden questions they can't have seen, which is any asked after the list
+            // that they got their token with (and so all of them for a first list)
+            .filter(|q| {
+                sees_hidden
+                    || !q.hidden
+                    || utils::to_unix_timestamp(q.id.datetime()) < since + SETTLE.as_secs()
+            })
+            .map(|q| {
+                if q.hidden && !sees_hidden {
+                    serde_json::json!({ "qid": q.id.to_string(), "hidden": true })
+                } else {
+                    serialize_question(q)
+                }
+            })
+            .collect();
+        trace!(%eid, since, n = %questions.len(), "listed changed questions");
+        // the token is left out of the ETag: if nothing changed, the client can just as well keep
+        // using the token it already has
+        let etag = etag(&serde_json::Value::from(questions.clone()));
+        let body = serde_json::json!({
+            "questions": questions,
+            "since": token,
+        });
+        return respond(body, etag, cache_control, headers);
+    }
+
+    let questions: Vec<_> = qs
+        .into_iter()
+        // only presenters get more from the store than they get to see
+        .filter(|q| has_secret || !q.pending)
+        .filter(|q| sees_hidden || !q.hidden)
+        .map(serialize_question)
+        .collect();
+
+    // sort based on "hotness" of the question over time:
+    // https://www.evanmiller.org/ranking-news-items-with-upvotes.html
+    // the wrapper struct is needed because f64 doesn't impl Ord
+    #[derive(Debug)]
+    #[repr(transparent)]
+    struct Score(f64);
+    impl PartialEq for Score {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other).is_eq()
+        }
+    }
+    impl Eq for Score {}
+    impl PartialOrd for Score {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+    impl Ord for Score {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            self.0.total_cmp(&other.0)
         }
     }
+    let now = dynamo.now();
+    let score = |q: &serde_json::Value| {
+        let dt_in_minutes_rounded_down = now
+            .duration_since(
+                q["qid"]
+                    .as_str()
+                    .expect("it's a ULID")
+                    .parse::<Ulid>()
+                    .expect("produced as ULID by us")
+                    .datetime(),
+            )
+            .unwrap_or(Duration::ZERO)
+            .as_secs()
+            // in minutes so questions don't jump around quite as much
+            / 60;
+        // +1 so that first minute questions don't get inf scores (for the ln)
+        let dt = dt_in_minutes_rounded_down + 1;
+        // +1 again to avoid NaN scores for first-minute questions (for / (1 - e^0)).
+        let dt = dt + 1;
+        // ln so that stories get less penalized for age over time
+        // after all, this is Q&A, not minute-to-minute hot news
+        let dt = (dt as f64).ln();
+        let votes = q["votes"].as_u64().expect("votes is a number") as f64;
+        // max so that even if vote count somehow got to 0, count it as 1
+        let votes = votes.max(1.);
+        let exp = (-dt).exp_m1() + 1.;
+        Score(exp * votes / (1. - exp))
+    };
+
+    let (mut questions, mut answered_hidden): (Vec<_>, Vec<_>) =
+        questions.into_iter().partition(|item| {
+            item.get("answered").is_none()
+                && !item.get("hidden").eq(&Some(&serde_json::Value::Bool(true)))
+                && !item
+                    .get("pending")
+                    .eq(&Some(&serde_json::Value::Bool(true)))
+        });
+    questions
+        .sort_by_key(|item| std::cmp::Reverse(item["votes"].as_u64().expect("votes is a number")));
+    if let Some(subslice) = questions.get_mut(TOP_N..) {
+        subslice.sort_by_cached_key(|q| std::cmp::Reverse(score(q)));
+    }
+    questions.append(&mut answered_hidden);
+    let body = serde_json::Value::from(questions);
+    respond(body.clone(), etag(&body), cache_control, headers)
+}
+
+fn etag(body: &serde_json::Value) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.to_string().hash(&mut hasher);
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/// Tag the list with an `ETag`, and leave out the list itself if the client already has it.
+///
+/// A `304 Not Modified` comes back as an `Err`, since it has no body, just like the errors.
+fn respond(
+    body: serde_json::Value,
+    etag: String,
+    cache_control: &'static str,
+    headers: &HeaderMap,
+) -> (Headers, Result<Json<serde_json::Value>, StatusCode>) {
+    // weak comparison, as RFC 9110 says to use for If-None-Match
+    let matches = headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/"));
+    let headers = AppendHeaders(vec![
+        (
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(cache_control),
+        ),
+        (
+            header::ETAG,
+            HeaderValue::from_str(&etag).expect("hex digits are valid header values"),
+        ),
+    ]);
+    if matches {
+        (headers, Err(StatusCode::NOT_MODIFIED))
+    } else {
+        (headers, Ok(Json(body)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::Secret;
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
         let secret = e["secret"].as_str().unwrap();
         let q = crate::ask::ask(
             Path(eid),
             State(backend.clone()),
+            crate::vote::Voter::generate(&backend),
             Json(crate::ask::Question {
                 body: "hello world".into(),
                 asker: None,
             }),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .1;
         let qid = q["id"].as_str().unwrap();
 
         let check = |qids: serde_json::Value| {
@@ -342,36 +428,89 @@ mod tests {
         };
 
         check(
-            super::list_all(Path((eid, secret.to_string())), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            super::list(
+                Path(eid),
+                Some(Secret(secret.to_string())),
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0,
         );
         check(
-            super::list(Path(eid), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            super::list(
+                Path(eid),
+                None,
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0,
+        );
+
+        // asking for changes since the beginning of time gives everything, plus a token
+        let changes = super::list(
+            Path(eid),
+            None,
+            Query(ListQuery {
+                since: Some("0".into()),
+            }),
+            Default::default(),
+            State(backend.clone()),
+        )
+        .await;
+        let etag = changes.0 .0[1].1.clone();
+        let changes = changes.1.unwrap().0;
+        assert!(changes["since"].as_str().unwrap().contains('.'));
+        check(changes["questions"].clone());
+
+        // and a list the client already has doesn't need to be sent again
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag);
+        assert_eq!(
+            super::list(
+                Path(eid),
+                None,
+                Query(ListQuery {
+                    since: Some("0".into()),
+                }),
+                headers,
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap_err(),
+            StatusCode::NOT_MODIFIED
         );
 
         // lookup with wrong secret gives 401
         assert_eq!(
-            super::list_all(Path((eid, "wrong".to_string())), State(backend.clone()),)
-                .await
-                .1
-                .unwrap_err(),
+            super::list(
+                Path(eid),
+                Some(Secret("wrong".to_string())),
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap_err(),
             StatusCode::UNAUTHORIZED
         );
 
         // lookup for non-existing event with secret gives 404
         assert_eq!(
-            super::list_all(
-                Path((
-                    Ulid::from_string("00000000000000000000000001").unwrap(),
-                    secret.to_string()
-                )),
+            super::list(
+                Path(Ulid::from_string("00000000000000000000000001").unwrap()),
+                Some(Secret(secret.to_string())),
+                Default::default(),
+                Default::default(),
                 State(backend.clone()),
             )
             .await
@@ -383,18 +522,27 @@ mod tests {
         backend.delete(&eid).await;
 
         // lookup for empty but existing event gives 200
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
-        let _ = super::list(Path(eid), State(backend.clone()))
-            .await
-            .1
-            .unwrap();
+        let _ = super::list(
+            Path(eid),
+            None,
+            Default::default(),
+            Default::default(),
+            State(backend.clone()),
+        )
+        .await
+        .1
+        .unwrap();
         backend.delete(&eid).await;
 
         // lookup for non-existing event without secret gives 404
         assert_eq!(
             super::list(
                 Path(Ulid::from_string("00000000000000000000000001").unwrap()),
+                None,
+                Default::default(),
+                Default::default(),
                 State(backend.clone()),
             )
             .await
@@ -404,14 +552,368 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn ranking() {
+        use crate::{clock::Clock, vote::UpDown};
+        use std::time::SystemTime;
+
+        let clock = Clock::fake(SystemTime::now());
+        let backend = Backend::local_with_clock(clock.clone());
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let ask = |votes: usize| {
+            let backend = backend.clone();
+            async move {
+                let q = crate::ask::ask(
+                    Path(eid),
+                    State(backend.clone()),
+                    crate::vote::Voter::generate(&backend),
+                    Json(crate::ask::Question {
+                        body: "hello world".into(),
+                        asker: None,
+                    }),
+                )
+                .await
+                .unwrap()
+                .1;
+                let qid = q["id"].as_str().unwrap().parse().unwrap();
+                for _ in 1..votes {
+                    backend
+                        .vote(&eid, &qid, &Ulid::generate(), UpDown::Up)
+                        .await
+                        .unwrap();
+                }
+                qid
+            }
+        };
+        let order = || async {
+            super::list(
+                Path(eid),
+                None,
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|q| q["qid"].as_str().unwrap().parse::<Ulid>().unwrap())
+            .collect::<Vec<_>>()
+        };
+
+        // the top questions are always ordered by votes alone
+        let mut top = Vec::new();
+        for votes in (1..=TOP_N).rev() {
+            top.push(ask(10 + votes).await);
+        }
+        let old = ask(3).await;
+
+        // a score works out to votes / (age in minutes + 1), so after three hours a fresh
+        // question with a single vote beats the old one with three ...
+        clock.advance(Duration::from_secs(3 * 60 * 60));
+        let new = ask(1).await;
+        assert_eq!(order().await, [&top[..], &[new, old]].concat());
+
+        // ... but only for a while: 3/781 > 1/601
+        clock.advance(Duration::from_secs(10 * 60 * 60));
+        assert_eq!(order().await, [&top[..], &[old, new]].concat());
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn changes() {
+        use crate::{clock::Clock, vote::UpDown};
+        use std::time::SystemTime;
+
+        let clock = Clock::fake(SystemTime::now());
+        let backend = Backend::local_with_clock(clock.clone());
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let ask = || async {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                crate::vote::Voter::generate(&backend),
+                Json(crate::ask::Question {
+                    body: "hello world".into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .unwrap()
+            .1;
+            q["id"].as_str().unwrap().parse::<Ulid>().unwrap()
+        };
+        let since = |token: &str, etag: Option<HeaderValue>| {
+            let token = token.to_string();
+            let mut headers = HeaderMap::new();
+            if let Some(etag) = etag {
+                headers.insert(header::IF_NONE_MATCH, etag);
+            }
+            let backend = backend.clone();
+            async move {
+                let (headers, res) = super::list(
+                    Path(eid),
+                    None,
+                    Query(ListQuery { since: Some(token) }),
+                    headers,
+                    State(backend),
+                )
+                .await;
+                // errors don't come with an ETag
+                let etag = headers.0.get(1).map(|(_, etag)| etag.clone());
+                (etag, res)
+            }
+        };
+        let qids = |changes: &serde_json::Value| {
+            changes["questions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|q| q["qid"].as_str().unwrap().parse::<Ulid>().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let q1 = ask().await;
+        let q2 = ask().await;
+        let (etag, res) = since("0", None).await;
+        let changes = res.unwrap().0;
+        let mut all = qids(&changes);
+        all.sort();
+        let mut asked = [q1, q2];
+        asked.sort();
+        assert_eq!(all, asked);
+        let token = changes["since"].as_str().unwrap().to_string();
+
+        // changes from just before the token was handed out are sent again, so nothing new here
+        assert_eq!(
+            since(&token, etag).await.1.unwrap_err(),
+            StatusCode::NOT_MODIFIED
+        );
+
+        // once things settle, the token moves past them
+        clock.advance(Duration::from_secs(60));
+        let changes = since(&token, None).await.1.unwrap().0;
+        let token = changes["since"].as_str().unwrap().to_string();
+        assert_eq!(qids(&since(&token, None).await.1.unwrap().0), []);
+
+        // and only what changed is sent
+        backend
+            .vote(&eid, &q1, &Ulid::generate(), UpDown::Up)
+            .await
+            .unwrap();
+        let changes = since(&token, None).await.1.unwrap().0;
+        assert_eq!(qids(&changes), [q1]);
+        assert_eq!(changes["questions"][0]["votes"], 2);
+
+        // guests hear that a question was hidden, but nothing else about it
+        clock.advance(Duration::from_secs(60));
+        let changes = since(&token, None).await.1.unwrap().0;
+        let token = changes["since"].as_str().unwrap().to_string();
+        let _ = crate::toggle::toggle(
+            Path((eid, q2, crate::toggle::Property::Hidden)),
+            Secret(secret.to_string()),
+            State(backend.clone()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+        let changes = since(&token, None).await.1.unwrap().0;
+        assert_eq!(
+            changes["questions"],
+            serde_json::json!([{ "qid": q2.to_string(), "hidden": true }])
+        );
+
+        // but a first list doesn't give away any hidden questions
+        let changes = since("0", None).await.1.unwrap().0;
+        assert_eq!(qids(&changes), [q1]);
+        assert!(!changes.to_string().contains(&q2.to_string()));
+
+        // and a token made up to be from before it was hidden doesn't work, whether it's unsigned,
+        // has its time changed, or is signed for another event
+        let (issued, signature) = token.split_once('.').unwrap();
+        let before = issued.parse::<u64>().unwrap() - 120;
+        let e2 = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid2 = Ulid::from_string(e2["id"].as_str().unwrap()).unwrap();
+        for forged in [
+            before.to_string(),
+            format!("{before}.{signature}"),
+            change_token(&backend, &eid2, before),
+        ] {
+            assert_eq!(
+                since(&forged, None).await.1.unwrap_err(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+        backend.delete(&eid2).await;
+        // nor does asking for the question itself
+        let details =
+            crate::questions::questions(Path(q2.to_string()), None, State(backend.clone()))
+                .await
+                .1
+                .unwrap()
+                .0;
+        assert_eq!(details[q2.to_string()], serde_json::Value::Null);
+
+        // nor do questions that were asked, and hidden, after the token was handed out
+        let q3 = ask().await;
+        let _ = crate::toggle::toggle(
+            Path((eid, q3, crate::toggle::Property::Hidden)),
+            Secret(secret.to_string()),
+            State(backend.clone()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+        assert!(!qids(&since(&token, None).await.1.unwrap().0).contains(&q3));
+
+        // and old tokens can't be used to go digging
+        clock.advance(MAX_TOKEN_AGE * 2);
+        assert_eq!(
+            since(&token, None).await.1.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn present() {
+        use crate::store::{Role, Token};
+        use crate::toggle::Property;
+
+        let backend = Backend::local().await;
+        let settings = crate::settings::Settings {
+            moderated: true,
+            ..Default::default()
+        };
+        let e = crate::new::new(State(backend.clone()), Some(Json(settings)))
+            .await
+            .unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let mut qids = Vec::new();
+        for body in ["to be approved", "to be hidden", "still pending"] {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                crate::vote::Voter::generate(&backend),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .unwrap()
+            .1;
+            qids.push(q["id"].as_str().unwrap().to_string());
+        }
+        let toggle = async |secret: &str, qid: &str, property| {
+            crate::toggle::toggle(
+                Path((eid, qid.parse().unwrap(), property)),
+                Secret(secret.to_string()),
+                State(backend.clone()),
+                String::from("on"),
+            )
+            .await
+        };
+        for (qid, property) in [
+            (&qids[0], Property::Approved),
+            (&qids[1], Property::Approved),
+            (&qids[1], Property::Hidden),
+        ] {
+            let _ = toggle(secret, qid, property).await.unwrap();
+        }
+        let token = |name: &str, show_hidden| Token {
+            name: name.into(),
+            secret: format!("{name}-secret"),
+            created: 0,
+            role: Role::Presenter,
+            show_hidden,
+        };
+        backend
+            .set_tokens(&eid, &[], &[token("stage", false), token("all", true)])
+            .await
+            .unwrap();
+
+        let present = async |secret: &str| {
+            let mut listed: Vec<_> = super::present(
+                Path(eid),
+                Secret(secret.to_string()),
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1?
+            .0
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|q| q["qid"].as_str().unwrap().to_string())
+            .collect();
+            listed.sort();
+            Ok::<_, StatusCode>(listed)
+        };
+        // presenters never see pending questions, and only see hidden ones if allowed to
+        assert_eq!(present("stage-secret").await.unwrap(), [&*qids[0]]);
+        let mut visible = qids[..2].to_vec();
+        visible.sort();
+        assert_eq!(present("all-secret").await.unwrap(), visible);
+        assert_eq!(present(secret).await.unwrap(), visible);
+        assert_eq!(present("wrong").await, Err(StatusCode::UNAUTHORIZED));
+
+        // and can't do what hosts do
+        assert_eq!(
+            toggle("all-secret", &qids[0], Property::Hidden)
+                .await
+                .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            super::list(
+                Path(eid),
+                Some(Secret("all-secret".to_string())),
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        backend.delete(&eid).await;
+    }
+
     #[tokio::test]
     async fn local() {
         inner(Backend::local().await).await;
     }
 
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
     #[tokio::test]
     #[ignore]
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
 }
@@ -6,7 +6,7 @@ async fn main() -> Result<(), Error> {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
-    let app = wewerewondering_api::new().await;
+    let app = wewerewondering_api::standalone().await;
     let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 3000));
     let listener = tokio::net::TcpListener::bind(&addr).await?;
     Ok(axum::serve(listener, app.into_make_service()).await?)
@@ -0,0 +1,210 @@
+use crate::settings;
+use crate::utils::{self, Secret};
+use crate::Backend;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use http::StatusCode;
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Turn moderation on or off for an event.
+///
+/// This is a shorthand for changing just the `moderated` [setting](crate::settings::Settings).
+///
+/// While it's on, new questions are pending until the host approves them (see
+/// [`Property::Approved`](crate::toggle::Property::Approved)). Questions that were asked before
+/// moderation was turned on stay as they are, and so do pending ones when it is turned off.
+pub(super) async fn moderate(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    State(dynamo): State<Backend>,
+    body: String,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let event = utils::check_secret(&dynamo, &eid, &secret).await?;
+
+    let moderated = match &*body {
+        "on" => true,
+        "off" => false,
+        _ => {
+            error!(%eid, body, "invalid moderation value");
+            return Err(http::StatusCode::BAD_REQUEST);
+        }
+    };
+
+    let mut settings = event.settings;
+    settings.moderated = moderated;
+    let _ = settings::configure(&dynamo, &eid, event.revision, settings, None).await?;
+    Ok(Json(serde_json::json!({ "moderated": moderated })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::Property;
+    use crate::utils::Secret;
+    use serde_json::Value;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let ask = async |body: &str| {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                crate::vote::Voter::generate(&backend),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .unwrap()
+            .1;
+            (
+                Ulid::from_string(q["id"].as_str().unwrap()).unwrap(),
+                q["pending"].as_bool().unwrap(),
+            )
+        };
+        let list = async |has_secret: bool| -> Vec<Value> {
+            let res = if has_secret {
+                crate::list::list(
+                    Path(eid),
+                    Some(Secret(secret.to_string())),
+                    Default::default(),
+                    Default::default(),
+                    State(backend.clone()),
+                )
+                .await
+            } else {
+                crate::list::list(
+                    Path(eid),
+                    None,
+                    Default::default(),
+                    Default::default(),
+                    State(backend.clone()),
+                )
+                .await
+            };
+            res.1.unwrap().0.as_array().unwrap().clone()
+        };
+
+        let (before, pending) = ask("asked before moderation").await;
+        assert!(!pending);
+
+        // only hosts can turn on moderation
+        assert_eq!(
+            super::moderate(
+                Path(eid),
+                Secret("wrong".into()),
+                State(backend.clone()),
+                "on".into()
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        let res = super::moderate(
+            Path(eid),
+            Secret(secret.to_string()),
+            State(backend.clone()),
+            "on".into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.0, serde_json::json!({ "moderated": true }));
+
+        // new questions are now pending, and only hosts see them
+        let (qid, pending) = ask("asked during moderation").await;
+        assert!(pending);
+        let guest = list(false).await;
+        assert_eq!(guest.len(), 1);
+        assert_eq!(guest[0]["qid"], before.to_string());
+        let host = list(true).await;
+        assert_eq!(host.len(), 2);
+        let q = host.iter().find(|q| q["qid"] == qid.to_string()).unwrap();
+        assert_eq!(q["pending"], true);
+
+        // until they approve them
+        let res = crate::toggle::toggle(
+            Path((eid, qid, Property::Approved)),
+            Secret(secret.to_string()),
+            State(backend.clone()),
+            "on".into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.0, serde_json::json!({ "approved": true }));
+        let guest = list(false).await;
+        assert_eq!(guest.len(), 2);
+        let q = guest.iter().find(|q| q["qid"] == qid.to_string()).unwrap();
+        assert_eq!(q["pending"], false);
+
+        // and without moderation, questions are public straight away again
+        let _ = super::moderate(
+            Path(eid),
+            Secret(secret.to_string()),
+            State(backend.clone()),
+            "off".into(),
+        )
+        .await
+        .unwrap();
+        let (_, pending) = ask("asked after moderation").await;
+        assert!(!pending);
+        assert_eq!(list(false).await.len(), 3);
+
+        // nonsense is refused, as are events that don't exist
+        assert_eq!(
+            super::moderate(
+                Path(eid),
+                Secret(secret.to_string()),
+                State(backend.clone()),
+                "maybe".into()
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            super::moderate(
+                Path(Ulid::from_string("00000000000000000000000001").unwrap()),
+                Secret(secret.to_string()),
+                State(backend.clone()),
+                "on".into()
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
+}
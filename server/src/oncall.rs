@@ -0,0 +1,25 @@
+//! Chores for whoever is on call, such as rotating a leaked secret or hiding abuse.
+//!
+//! ```sh
+//! admin <command> [args...]
+//! ```
+//!
+//! Run it without a command to see what it can do. Like the `import` binary, this works on the
+//! store selected by the environment, the same way the server picks one (`USE_DYNAMODB`,
+//! `USE_SQLITE`, ...).
+
+use tracing_subscriber::EnvFilter;
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = wewerewondering_api::admin(&args).await {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
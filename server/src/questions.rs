@@ -1,9 +1,5 @@
-use super::{Backend, Local};
-use aws_sdk_dynamodb::{
-    error::SdkError,
-    operation::batch_get_item::{BatchGetItemError, BatchGetItemOutput},
-    types::{AttributeValue, KeysAndAttributes},
-};
+use super::Backend;
+use crate::utils::{self, Credential, Secret};
 use axum::{
     extract::{Path, State},
     response::AppendHeaders,
@@ -20,175 +16,102 @@ use ulid::Ulid;
 #[allow(unused_imports)]
 use tracing::{debug, error, info, trace, warn};
 
-impl Backend {
-    pub(super) async fn questions(
-        &self,
-        qids: &[Ulid],
-    ) -> Result<BatchGetItemOutput, SdkError<BatchGetItemError>> {
-        match self {
-            Self::Dynamo(dynamo) => {
-                let keys = qids
-                    .iter()
-                    .map(|qid| {
-                        HashMap::from_iter([(
-                            String::from("id"),
-                            AttributeValue::S(qid.to_string()),
-                        )])
-                    })
-                    .collect();
-                dynamo
-                    .batch_get_item()
-                    .request_items(
-                        "questions",
-                        KeysAndAttributes::builder()
-                            .set_keys(Some(keys))
-                            .projection_expression("id,#text,#when,who")
-                            .expression_attribute_names("#text", "text")
-                            .expression_attribute_names("#when", "when")
-                            .build()
-                            .expect("we're building correct things"),
-                    )
-                    .send()
-                    .await
-            }
-            Self::Local(local) => {
-                let mut local = local.lock().unwrap();
-                let Local { questions, .. } = &mut *local;
-
-                let unprocessed: Vec<_> = qids
-                    .iter()
-                    .filter(|qid| !questions.contains_key(qid))
-                    .map(|qid| {
-                        HashMap::from_iter([(
-                            String::from("id"),
-                            AttributeValue::S(qid.to_string()),
-                        )])
-                    })
-                    .collect();
-                let unprocessed = if unprocessed.is_empty() {
-                    None
-                } else {
-                    Some(HashMap::from_iter([(
-                        String::from("questions"),
-                        KeysAndAttributes::builder()
-                            .set_keys(Some(unprocessed))
-                            .projection_expression("text,when,who")
-                            .build()
-                            .expect("we build correctly"),
-                    )]))
-                };
+type Headers = AppendHeaders<[(HeaderName, &'static str); 2]>;
 
-                Ok(BatchGetItemOutput::builder()
-                    .set_unprocessed_keys(unprocessed)
-                    .set_responses(Some(HashMap::from_iter([(
-                        String::from("questions"),
-                        qids.iter()
-                            .filter_map(|qid| {
-                                Some(
-                                    questions
-                                        .get(qid)?
-                                        .iter()
-                                        .filter(|&(k, _)| {
-                                            matches!(*k, "id" | "text" | "when" | "who")
-                                        })
-                                        .map(|(k, v)| (k.to_string(), v.clone()))
-                                        .collect(),
-                                )
-                            })
-                            .collect(),
-                    )])))
-                    .build())
-            }
-        }
-    }
+fn cache_control(value: &'static str) -> Headers {
+    // hosts get to see more than guests do, so caches have to tell them apart
+    AppendHeaders([
+        (header::CACHE_CONTROL, value),
+        (header::VARY, "Authorization"),
+    ])
 }
 
+/// Look up the text, asker, and time of the given questions.
+///
+/// Hidden and pending questions come back as `null` for guests, since otherwise this would be a
+/// way around the list leaving them out. Hosts, and presenters that may see hidden questions, get
+/// them by passing their credential the same way as for the list.
 pub(super) async fn questions(
     Path(qids): Path<String>,
+    secret: Option<Secret>,
     State(dynamo): State<Backend>,
-) -> (
-    AppendHeaders<[(HeaderName, &'static str); 1]>,
-    Result<Json<Value>, StatusCode>,
-) {
+) -> (Headers, Result<Json<Value>, StatusCode>) {
     let qids: Vec<_> = match qids.split(',').map(Ulid::from_string).collect() {
         Ok(v) => v,
         Err(e) => {
             warn!(%qids, error = %e, "got invalid ulid set");
             return (
                 // a bad request will never become good
-                AppendHeaders([(header::CACHE_CONTROL, "max-age=864001")]),
+                cache_control("max-age=864001"),
                 Err(http::StatusCode::BAD_REQUEST),
             );
         }
     };
     match dynamo.questions(&qids).await {
-        Ok(v) => {
-            if v.responses().map_or(true, |r| r.is_empty()) {
+        Ok(qs) => {
+            if qs.is_empty() {
                 warn!(?qids, "no valid qids");
                 return (
                     // it should be unlikely that someone fetches a question that hasn't been asked
                     // it's _possible_ that it happens and _then_ a question is assigned that ulid,
                     // but it too seems rare.
-                    AppendHeaders([(header::CACHE_CONTROL, "max-age=600")]),
+                    cache_control("max-age=600"),
                     Err(http::StatusCode::NOT_FOUND),
                 );
             }
-            let r = v.responses().unwrap();
-            let t = if let Some(t) = r.get("questions") {
-                t
-            } else {
-                error!(?qids, ?v, "got non-empty non-questions response");
-                return (
-                    AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
-                    Err(http::StatusCode::INTERNAL_SERVER_ERROR),
-                );
-            };
 
-            let r = t
-                .iter()
-                .map(|q| {
-                    let qid = q
-                        .get("id")
-                        .and_then(|v| v.as_s().ok())
-                        .and_then(|v| ulid::Ulid::from_string(v).ok());
-                    let text = q.get("text").and_then(|v| v.as_s().ok());
-                    let who = q.get("who").and_then(|v| v.as_s().ok());
-                    let when = q
-                        .get("when")
-                        .and_then(|v| v.as_n().ok())
-                        .and_then(|v| v.parse::<usize>().ok());
-                    match (qid, text, when) {
-                        (Some(qid), Some(text), Some(when)) => {
-                            let mut v = serde_json::json!({
-                                "text": text,
-                                "when": when,
-                            });
-                            if let Some(who) = who {
-                                v["who"] = who.clone().into();
+            // what the caller may see of each event: hidden questions, and pending ones.
+            // credentials are only checked for events that have something to hold back.
+            let mut access: HashMap<Ulid, (bool, bool)> = HashMap::new();
+            let mut withheld = false;
+            let mut r = serde_json::Map::new();
+            for q in qs {
+                if q.hidden || q.pending {
+                    let (sees_hidden, sees_pending) = match (access.get(&q.eid), &secret) {
+                        (Some(&access), _) => access,
+                        (None, None) => (false, false),
+                        (None, Some(Secret(secret))) => {
+                            match utils::authenticate(&dynamo, &q.eid, secret).await {
+                                Ok((_, Credential::Secret | Credential::CoHost(_))) => (true, true),
+                                Ok((_, Credential::Presenter { show_hidden, .. })) => {
+                                    (show_hidden, false)
+                                }
+                                Err(e) => return (cache_control("no-cache"), Err(e)),
                             }
-                            Ok((qid.to_string(), v))
-                        }
-                        _ => {
-                            error!(?qids, ?q, "bad data types for id/text/when");
-                            Err(StatusCode::INTERNAL_SERVER_ERROR)
                         }
+                    };
+                    access.insert(q.eid, (sees_hidden, sees_pending));
+                    if (q.hidden && !sees_hidden) || (q.pending && !sees_pending) {
+                        trace!(qid = %q.id, "withholding question");
+                        withheld = true;
+                        r.insert(q.id.to_string(), Value::Null);
+                        continue;
                     }
-                })
-                .collect::<Result<_, _>>()
-                .map(Json);
-            if r.is_ok() {
-                (
-                    AppendHeaders([(header::CACHE_CONTROL, "max-age=864001")]),
-                    r,
-                )
-            } else {
-                (AppendHeaders([(header::CACHE_CONTROL, "no-cache")]), r)
+                }
+                let mut v = serde_json::json!({
+                    "text": q.text,
+                    "when": utils::to_unix_timestamp(q.when),
+                });
+                if let Some(who) = q.who {
+                    v["who"] = who.into();
+                }
+                r.insert(q.id.to_string(), v);
             }
+            (
+                // a question that's held back now may be shown later, and the other way around
+                // doesn't matter, since whoever got it already has it
+                cache_control(if withheld {
+                    "no-cache"
+                } else {
+                    "max-age=864001"
+                }),
+                Ok(Json(Value::Object(r))),
+            )
         }
         Err(e) => {
-            error!(?qids, error = %e, "dynamodb question request failed");
+            error!(?qids, error = %e, "question request failed");
             (
-                AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+                cache_control("no-cache"),
                 Err(http::StatusCode::INTERNAL_SERVER_ERROR),
             )
         }
@@ -200,33 +123,37 @@ mod tests {
     use super::*;
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
-        let _secret = e["secret"].as_str().unwrap();
+        let secret = e["secret"].as_str().unwrap();
         let q1 = crate::ask::ask(
             Path(eid),
             State(backend.clone()),
+            crate::vote::Voter::generate(&backend),
             Json(crate::ask::Question {
                 body: "hello world".into(),
                 asker: None,
             }),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .1;
         let qid1 = q1["id"].as_str().unwrap();
         let q2 = crate::ask::ask(
             Path(eid),
             State(backend.clone()),
+            crate::vote::Voter::generate(&backend),
             Json(crate::ask::Question {
                 body: "hello moon".into(),
                 asker: Some("person".into()),
             }),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .1;
         let qid2 = q2["id"].as_str().unwrap();
 
-        let qids = super::questions(Path(format!("{qid1},{qid2}")), State(backend.clone()))
+        let qids = super::questions(Path(format!("{qid1},{qid2}")), None, State(backend.clone()))
             .await
             .1
             .unwrap();
@@ -241,6 +168,32 @@ mod tests {
         assert_eq!(q2["who"], "person");
         assert!(q2["when"].is_u64());
 
+        // once a question is hidden, only hosts get to see it
+        let _ = crate::toggle::toggle(
+            Path((eid, qid1.parse().unwrap(), crate::toggle::Property::Hidden)),
+            Secret(secret.to_string()),
+            State(backend.clone()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+        let get = async |secret: Option<&str>| {
+            super::questions(
+                Path(format!("{qid1},{qid2}")),
+                secret.map(|s| Secret(s.to_string())),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .map(|qs| qs.0)
+        };
+        let qids = get(None).await.unwrap();
+        assert_eq!(qids[qid1], serde_json::Value::Null);
+        assert_eq!(qids[qid2]["text"], "hello moon");
+        let qids = get(Some(secret)).await.unwrap();
+        assert_eq!(qids[qid1]["text"], "hello world");
+        assert_eq!(get(Some("wrong")).await, Err(StatusCode::UNAUTHORIZED));
+
         backend.delete(&eid).await;
     }
 
@@ -249,9 +202,22 @@ mod tests {
         inner(Backend::local().await).await;
     }
 
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
     #[tokio::test]
     #[ignore]
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
 }
@@ -0,0 +1,613 @@
+use crate::store;
+use crate::utils::{self, Secret};
+use crate::{days, Backend, EVENTS_TTL, MAX_TTL, QUESTIONS_TTL};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+// Keeps the settings document (and the event page that shows it) reasonably small.
+const MAX_TITLE: usize = 100;
+const MAX_DESCRIPTION: usize = 500;
+
+/// Whether askers have to give their name.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Names {
+    #[default]
+    Optional,
+    Required,
+    Forbidden,
+}
+
+/// Whether an event is still taking questions and votes.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EventState {
+    #[default]
+    Open,
+    /// No new questions, but votes still count until the event's closing time, if it has one.
+    Closed,
+    /// Neither new questions nor votes; the questions can still be viewed.
+    Locked,
+}
+
+/// Where an event is at, taking both its [state](EventState) and its schedule into account.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Phase {
+    /// It's not yet time for questions or votes.
+    Upcoming,
+    Open,
+    /// No new questions, but votes still count.
+    ///
+    /// This is only ever because the host closed the event, so that guests can still weigh in on
+    /// what was asked before the host gets to it.
+    Closed,
+    /// Neither new questions nor votes.
+    ///
+    /// This is either because the host locked the event, or because its closing time has passed.
+    /// A schedule bounds the whole event, so votes stop along with questions.
+    Locked,
+}
+
+impl Phase {
+    pub(crate) fn takes_questions(self) -> bool {
+        self == Phase::Open
+    }
+
+    pub(crate) fn takes_votes(self) -> bool {
+        matches!(self, Phase::Open | Phase::Closed)
+    }
+}
+
+/// How the host has set up an event.
+///
+/// Settings are stored alongside the event as a JSON document, so fields that are missing (such
+/// as for events made before a setting existed) take their default value.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Settings {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub state: EventState,
+    pub names: Names,
+    /// The longest question (in characters) that will be accepted.
+    pub max_length: Option<usize>,
+    /// Whether new questions have to be approved by the host before guests get to see them.
+    pub moderated: bool,
+    /// When the event starts taking questions and votes, in seconds since the UNIX epoch.
+    pub opens_at: Option<u64>,
+    /// When the event stops taking questions and votes, in seconds since the UNIX epoch.
+    ///
+    /// The event is kept around for a while after this (see [`Settings::expire`]).
+    pub closes_at: Option<u64>,
+    /// How many days to keep questions after they're asked, if longer than the server's default.
+    ///
+    /// This is bounded by what the server allows, and the event itself is kept at least as long.
+    pub retain_days: Option<u64>,
+}
+
+impl Settings {
+    /// The parts of the settings that guests get to see.
+    pub(crate) fn public(&self) -> serde_json::Value {
+        serde_json::json!({
+            "title": self.title,
+            "description": self.description,
+            "state": self.state,
+            "names": self.names,
+            "max_length": self.max_length,
+            "opens_at": self.opens_at,
+            "closes_at": self.closes_at,
+        })
+    }
+
+    /// Where the event is at, at time `now`.
+    pub(crate) fn phase(&self, now: SystemTime) -> Phase {
+        let now = utils::to_unix_timestamp(now);
+        match self.state {
+            EventState::Locked => Phase::Locked,
+            _ if self.closes_at.is_some_and(|t| now >= t) => Phase::Locked,
+            EventState::Closed => Phase::Closed,
+            EventState::Open if self.opens_at.is_some_and(|t| now < t) => Phase::Upcoming,
+            EventState::Open => Phase::Open,
+        }
+    }
+
+    /// How long questions are kept after they're asked.
+    pub(crate) fn question_ttl(&self) -> Duration {
+        self.retain_days.map_or(*QUESTIONS_TTL, days)
+    }
+
+    /// When an event with these settings should expire, if they were set at `now`.
+    ///
+    /// The event is kept for [`EVENTS_TTL`] (or as long as its questions, if that's longer) after
+    /// it closes, or after `now` if that's later (or it has no closing time).
+    ///
+    /// Gives `None` if that is further out than the system can represent, which
+    /// [`validate`](Settings::validate) rules out.
+    pub(crate) fn expire(&self, now: SystemTime) -> Option<SystemTime> {
+        let closes = match self.closes_at {
+            Some(closes) => utils::from_unix_timestamp(closes)?,
+            None => now,
+        };
+        now.max(closes)
+            .checked_add(self.question_ttl().max(*EVENTS_TTL))
+    }
+
+    /// Check that the settings make sense, if they were set at `now`.
+    pub(crate) fn validate(&self, now: SystemTime) -> Result<(), &'static str> {
+        if self
+            .title
+            .as_ref()
+            .is_some_and(|t| t.chars().count() > MAX_TITLE)
+        {
+            return Err("title is too long");
+        }
+        if self
+            .description
+            .as_ref()
+            .is_some_and(|d| d.chars().count() > MAX_DESCRIPTION)
+        {
+            return Err("description is too long");
+        }
+        if self.max_length == Some(0) {
+            return Err("max question length must be positive");
+        }
+        // an event can't be scheduled further out than it could be kept around for
+        let latest = utils::to_unix_timestamp(now).saturating_add(MAX_TTL.as_secs());
+        if [self.opens_at, self.closes_at]
+            .into_iter()
+            .flatten()
+            .any(|t| t > latest)
+        {
+            return Err("event is scheduled too far out");
+        }
+        if let (Some(opens), Some(closes)) = (self.opens_at, self.closes_at) {
+            if opens >= closes {
+                return Err("event must open before it closes");
+            }
+        }
+        if let Some(retain) = self.retain_days.map(days) {
+            if retain < *QUESTIONS_TTL || retain > *MAX_TTL {
+                return Err("retention is out of bounds");
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(super) async fn settings(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    State(dynamo): State<Backend>,
+) -> Result<Json<Settings>, StatusCode> {
+    utils::check_secret(&dynamo, &eid, &secret).await?;
+    Ok(Json(current(&dynamo, &eid).await?))
+}
+
+/// Change some of the settings of an event.
+///
+/// The body is a JSON object with just the settings to change. Optional settings are cleared by
+/// setting them to `null`.
+pub(super) async fn update(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    State(dynamo): State<Backend>,
+    Json(patch): Json<serde_json::Map<String, serde_json::Value>>,
+) -> Result<Json<Settings>, StatusCode> {
+    let event = utils::check_secret(&dynamo, &eid, &secret).await?;
+    let current = event.settings;
+    let mut settings = serde_json::to_value(&current).expect("settings serialize to a JSON object");
+    settings
+        .as_object_mut()
+        .expect("settings serialize to a JSON object")
+        .extend(patch);
+    let settings: Settings = match serde_json::from_value(settings) {
+        Ok(settings) => settings,
+        Err(e) => {
+            warn!(%eid, error = %e, "ignoring invalid event settings");
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    };
+    let now = dynamo.now();
+    if let Err(e) = settings.validate(now) {
+        warn!(%eid, error = e, "ignoring invalid event settings");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // the event is kept around for a while after it closes, so that moves along with it
+    let retain = settings.retain_days != current.retain_days;
+    let expire = if retain || settings.closes_at != current.closes_at {
+        match settings.expire(now) {
+            Some(expire) => Some(expire),
+            None => {
+                warn!(%eid, "ignoring event settings that never expire");
+                return Err(StatusCode::BAD_REQUEST);
+            }
+        }
+    } else {
+        None
+    };
+    let ttl = settings.question_ttl();
+    let settings = configure(&dynamo, &eid, event.revision, settings, expire).await?;
+    if retain {
+        if let Err(e) = dynamo.retain(&eid, ttl).await {
+            error!(%eid, error = %e, "request to change question retention failed");
+            return Err(http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        debug!(%eid, ?ttl, "changed question retention");
+    }
+    Ok(settings)
+}
+
+/// Store new settings for an event whose secret has already been checked, made from the settings
+/// at `revision`.
+///
+/// If the settings changed in the meantime, this gives 409 rather than undo that change, and the
+/// host can try again from the current settings.
+pub(super) async fn configure(
+    dynamo: &Backend,
+    eid: &Ulid,
+    revision: u64,
+    settings: Settings,
+    expire: Option<SystemTime>,
+) -> Result<Json<Settings>, StatusCode> {
+    match dynamo.configure(eid, revision, &settings, expire).await {
+        Ok(_) => {
+            debug!(%eid, ?settings, "changed event settings");
+            Ok(Json(settings))
+        }
+        Err(store::Error::Conflict) => {
+            warn!(%eid, "event settings changed while changing them");
+            Err(http::StatusCode::CONFLICT)
+        }
+        Err(store::Error::NotFound) => {
+            warn!(%eid, "attempted to configure non-existing event");
+            Err(http::StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "request to change event settings failed");
+            Err(http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// The current settings of an event whose secret has already been checked.
+pub(super) async fn current(dynamo: &Backend, eid: &Ulid) -> Result<Settings, StatusCode> {
+    match dynamo.event(eid).await {
+        Ok(Some(e)) => Ok(e.settings),
+        Ok(None) => {
+            warn!(%eid, "event disappeared while reading its settings");
+            Err(http::StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "request for event settings failed");
+            Err(http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Secret;
+    use serde_json::json;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let update = async |patch: serde_json::Value| {
+            super::update(
+                Path(eid),
+                Secret(secret.to_string()),
+                State(backend.clone()),
+                Json(patch.as_object().unwrap().clone()),
+            )
+            .await
+            .map(|s| s.0)
+        };
+        let public = async || {
+            crate::event::event(Path(eid), State(backend.clone()))
+                .await
+                .1
+                .unwrap()
+                .0
+        };
+
+        // new events start out with the defaults
+        let settings = super::settings(
+            Path(eid),
+            Secret(secret.to_string()),
+            State(backend.clone()),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(settings, Settings::default());
+
+        // only the given settings change
+        let settings = update(json!({ "title": "Q&A", "max_length": 200 }))
+            .await
+            .unwrap();
+        assert_eq!(settings.title.as_deref(), Some("Q&A"));
+        assert_eq!(settings.max_length, Some(200));
+        let settings = update(json!({ "names": "required", "moderated": true }))
+            .await
+            .unwrap();
+        assert_eq!(settings.title.as_deref(), Some("Q&A"));
+        assert_eq!(settings.names, Names::Required);
+        assert!(settings.moderated);
+        assert_eq!(
+            super::settings(
+                Path(eid),
+                Secret(secret.to_string()),
+                State(backend.clone())
+            )
+            .await
+            .unwrap()
+            .0,
+            settings
+        );
+
+        // null clears a setting
+        let settings = update(json!({ "max_length": null })).await.unwrap();
+        assert_eq!(settings.max_length, None);
+
+        // retention can be extended, which re-stamps the event and its questions
+        let _ = update(json!({ "retain_days": 90 })).await.unwrap();
+        let settings = update(json!({ "retain_days": null })).await.unwrap();
+
+        // guests get to see most of it
+        assert_eq!(
+            public().await,
+            json!({
+                "title": "Q&A",
+                "description": null,
+                "state": "open",
+                "names": "required",
+                "max_length": null,
+                "opens_at": null,
+                "closes_at": null,
+                "phase": "open",
+            })
+        );
+
+        // nonsense is refused, and leaves the settings as they were
+        for bad in [
+            json!({ "names": "sometimes" }),
+            json!({ "state": "ajar" }),
+            json!({ "colour": "blue" }),
+            json!({ "max_length": 0 }),
+            json!({ "opens_at": 200, "closes_at": 100 }),
+            json!({ "retain_days": 1 }),
+            json!({ "retain_days": u64::MAX }),
+            json!({ "title": "a".repeat(MAX_TITLE + 1) }),
+            // times too far out to represent are refused rather than overflowing
+            json!({ "closes_at": 10_000_000_000_000_000_000u64 }),
+            json!({ "opens_at": u64::MAX }),
+            json!({ "closes_at": utils::to_unix_timestamp(backend.now()) + MAX_TTL.as_secs() + 1 }),
+        ] {
+            assert_eq!(update(bad).await.unwrap_err(), StatusCode::BAD_REQUEST);
+        }
+        let far_out = Settings {
+            closes_at: Some(10_000_000_000_000_000_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            crate::new::new(State(backend.clone()), Some(Json(far_out)))
+                .await
+                .unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            super::settings(
+                Path(eid),
+                Secret(secret.to_string()),
+                State(backend.clone())
+            )
+            .await
+            .unwrap()
+            .0,
+            settings
+        );
+
+        // and only hosts get to see or change the settings
+        assert_eq!(
+            super::settings(Path(eid), Secret("wrong".into()), State(backend.clone()))
+                .await
+                .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            super::update(
+                Path(eid),
+                Secret("wrong".into()),
+                State(backend.clone()),
+                Json(Default::default())
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn schedule() {
+        use crate::vote::Voter;
+        use std::time::Duration;
+
+        let start = SystemTime::now();
+        let clock = crate::clock::Clock::fake(start);
+        let backend = Backend::local_with_clock(clock.clone());
+        let hour = Duration::from_secs(3600);
+        let opens = utils::to_unix_timestamp(start + hour);
+        let closes = utils::to_unix_timestamp(start + 2 * hour);
+        let e = crate::new::new(
+            State(backend.clone()),
+            Some(Json(Settings {
+                opens_at: Some(opens),
+                closes_at: Some(closes),
+                ..Default::default()
+            })),
+        )
+        .await
+        .unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let phase = async || {
+            crate::event::event(Path(eid), State(backend.clone()))
+                .await
+                .1
+                .map(|v| v.0["phase"].clone())
+        };
+        let ask = async || {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                Voter::generate(&backend),
+                Json(crate::ask::Question {
+                    body: "hello world".into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .map(|(_, q)| Ulid::from_string(q["id"].as_str().unwrap()).unwrap())
+        };
+
+        // nothing goes before the event opens
+        assert_eq!(phase().await, Ok(json!("upcoming")));
+        assert_eq!(ask().await.unwrap_err(), StatusCode::FORBIDDEN);
+
+        clock.advance(hour);
+        assert_eq!(phase().await, Ok(json!("open")));
+        let qid = ask().await.unwrap();
+
+        let vote = async || {
+            crate::vote::vote(
+                Path((eid, qid, crate::vote::UpDown::Up)),
+                State(backend.clone()),
+                Voter::generate(&backend),
+            )
+            .await
+            .map(|(_, v)| v["votes"].clone())
+        };
+        assert_eq!(vote().await, Ok(json!(2)));
+
+        // once it closes, neither questions nor votes go, even if the host had closed it already
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let _ = super::update(
+            Path(eid),
+            Secret(secret.clone()),
+            State(backend.clone()),
+            Json(json!({ "state": "closed" }).as_object().unwrap().clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(phase().await, Ok(json!("closed")));
+        assert_eq!(vote().await, Ok(json!(3)));
+        clock.advance(hour);
+        assert_eq!(phase().await, Ok(json!("locked")));
+        assert_eq!(ask().await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(vote().await.unwrap_err(), StatusCode::FORBIDDEN);
+
+        // the event is kept around for a while after it closes, rather than after it was made
+        clock.advance(*crate::EVENTS_TTL - Duration::from_secs(1));
+        assert_eq!(phase().await, Ok(json!("locked")));
+
+        // and moving the closing time moves that too
+        let closes = utils::to_unix_timestamp(clock.now());
+        let _ = super::update(
+            Path(eid),
+            Secret(secret),
+            State(backend.clone()),
+            Json(json!({ "closes_at": closes }).as_object().unwrap().clone()),
+        )
+        .await
+        .unwrap();
+        clock.advance(*crate::EVENTS_TTL - Duration::from_secs(1));
+        assert_eq!(phase().await, Ok(json!("locked")));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(phase().await, Err(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn retention() {
+        use crate::vote::Voter;
+        use std::time::Duration;
+
+        let clock = crate::clock::Clock::fake(SystemTime::now());
+        let backend = Backend::local_with_clock(clock.clone());
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let (_, q) = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            Voter::generate(&backend),
+            Json(crate::ask::Question {
+                body: "hello world".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid = Ulid::from_string(q["id"].as_str().unwrap()).unwrap();
+        let asked = async || backend.questions(&[qid]).await.unwrap().len();
+
+        // by default, the question would go away before this
+        clock.advance(*QUESTIONS_TTL - days(1));
+        let _ = super::update(
+            Path(eid),
+            Secret(secret),
+            State(backend.clone()),
+            Json(json!({ "retain_days": 90 }).as_object().unwrap().clone()),
+        )
+        .await
+        .unwrap();
+        clock.advance(days(2));
+        assert_eq!(asked().await, 1);
+
+        // but now it's there until 90 days after it was asked
+        clock.advance(days(90) - *QUESTIONS_TTL - days(1) - Duration::from_secs(1));
+        assert_eq!(asked().await, 1);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(asked().await, 0);
+
+        // and the event lasts at least as long as its questions
+        assert!(backend.event(&eid).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
+}
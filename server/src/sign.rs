@@ -0,0 +1,81 @@
+use hmac::{Hmac, Mac};
+use rand::{rng, RngExt};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The key that the server signs what it hands out to clients with, such as change tokens and
+/// voter cookies, so that it can tell later on that it was the one that made them.
+///
+/// Every process that serves the same store needs the same key, which is taken from
+/// `SIGNING_KEY`. Without it, each process makes up its own, which is only good enough for a
+/// single standalone server (and for tests).
+#[derive(Clone)]
+pub(crate) struct Key(Arc<[u8]>);
+
+// so that the key doesn't end up in logs by way of `?`
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+impl Key {
+    pub(crate) fn from_env() -> Self {
+        match std::env::var("SIGNING_KEY") {
+            Ok(key) if !key.is_empty() => Key(key.into_bytes().into()),
+            _ => {
+                warn!("SIGNING_KEY is not set, so what this process signs only works with it");
+                Key(rng().random::<[u8; 32]>().into())
+            }
+        }
+    }
+
+    /// Sign `parts`, the first of which should say what is being signed.
+    ///
+    /// The parts are length-prefixed, so that they can't be shifted around to make a different
+    /// message with the same signature.
+    pub(crate) fn sign(&self, parts: &[&str]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC takes keys of any size");
+        for part in parts {
+            mac.update(&(part.len() as u64).to_be_bytes());
+            mac.update(part.as_bytes());
+        }
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// Check, in constant time, that `signature` is what [`Key::sign`] gives for `parts`.
+    pub(crate) fn verify(&self, parts: &[&str], signature: &str) -> bool {
+        self.sign(parts)
+            .as_bytes()
+            .ct_eq(signature.as_bytes())
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures() {
+        let key = Key(Arc::from(&b"key"[..]));
+        let signature = key.sign(&["test", "a", "b"]);
+        assert!(key.verify(&["test", "a", "b"], &signature));
+        assert!(!key.verify(&["test", "a", "c"], &signature));
+        assert!(!key.verify(&["test", "ab", ""], &signature));
+        assert!(!key.verify(&["test", "a", "b"], &signature[1..]));
+        assert!(!key.verify(&["test", "a", "b"], ""));
+        // and other keys sign differently
+        let other = Key(Arc::from(&b"other key"[..]));
+        assert!(!other.verify(&["test", "a", "b"], &signature));
+    }
+}
@@ -0,0 +1,362 @@
+use super::Backend;
+use crate::ticket::{self, TicketQuery};
+use crate::toggle::Property;
+use crate::utils::{self, Secret};
+use crate::vote::{UpDown, Voter};
+use crate::{ask, hub::Feed, store};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::response::{Json, Response};
+use http::StatusCode;
+use serde::Deserialize;
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+// The largest message a client may send. Asks are the largest operation, and are held to the same
+// limit as over HTTP (see `ask::too_long`), so this only leaves room for the rest of the request.
+const MAX_MESSAGE: usize = 2 * ask::MAX_BODY;
+
+/// An operation sent by the client, in the same shape as the body (and path) of the equivalent
+/// HTTP request.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Op {
+    Ask(ask::Question),
+    Vote {
+        qid: Ulid,
+        direction: UpDown,
+    },
+    Toggle {
+        qid: Ulid,
+        property: Property,
+        value: String,
+    },
+}
+
+/// A message from the client.
+///
+/// The `id` is passed back as-is in the reply, so that clients can tell which reply belongs to
+/// which request among the updates.
+#[derive(Deserialize, Debug)]
+struct Request {
+    #[serde(default)]
+    id: serde_json::Value,
+    #[serde(flatten)]
+    op: Op,
+}
+
+/// Open a socket for an event, which also allows toggling questions if a host secret is given,
+/// either directly or through a [ticket](crate::ticket).
+pub(super) async fn socket(
+    Path(eid): Path<Ulid>,
+    secret: Option<Secret>,
+    Query(query): Query<TicketQuery>,
+    State(dynamo): State<Backend>,
+    voter: Voter,
+    ws: WebSocketUpgrade,
+) -> Result<(Voter, Response), StatusCode> {
+    match ticket::secret(&dynamo, &eid, secret, query)? {
+        Some(secret) => {
+            utils::check_secret(&dynamo, &eid, &secret).await?;
+            socket_inner(eid, Some(secret), dynamo, voter, ws).await
+        }
+        None => {
+            utils::get_event(&dynamo, &eid).await?;
+            socket_inner(eid, None, dynamo, voter, ws).await
+        }
+    }
+}
+
+async fn socket_inner(
+    eid: Ulid,
+    secret: Option<String>,
+    dynamo: Backend,
+    voter: Voter,
+    ws: WebSocketUpgrade,
+) -> Result<(Voter, Response), StatusCode> {
+    let feed = match Feed::new(&dynamo, eid, secret.is_some()).await {
+        Ok(feed) => feed,
+        Err(store::Error::NotFound) => {
+            warn!(%eid, "request to open socket for non-existing event");
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "request to set up question socket failed");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    debug!(%eid, has_secret = secret.is_some(), "opening question socket");
+
+    let session = Session {
+        eid,
+        secret,
+        voter: voter.clone(),
+        dynamo,
+    };
+    let ws = ws.max_message_size(MAX_MESSAGE).max_frame_size(MAX_MESSAGE);
+    Ok((voter, ws.on_upgrade(move |ws| session.run(ws, feed))))
+}
+
+/// A single client's connection to an event.
+struct Session {
+    eid: Ulid,
+    secret: Option<String>,
+    voter: Voter,
+    dynamo: Backend,
+}
+
+impl Session {
+    async fn run(self, mut ws: WebSocket, mut feed: Feed) {
+        let eid = self.eid;
+        loop {
+            let msg = tokio::select! {
+                msg = ws.recv() => match msg {
+                    Some(Ok(Message::Text(text))) => self.handle(&text).await,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    // pings are answered by axum, and we have no use for binary messages
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        debug!(%eid, error = %e, "question socket failed");
+                        break;
+                    }
+                },
+                update = feed.next() => match update {
+                    Some((kind, data)) => serde_json::json!({ "event": kind, "data": data }),
+                    None => {
+                        // the client has to start over from a fresh list
+                        let _ = ws.send(Message::Close(None)).await;
+                        break;
+                    }
+                },
+            };
+            if ws
+                .send(Message::Text(msg.to_string().into()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        debug!(%eid, "closed question socket");
+    }
+
+    /// Carry out one client request through the same handler as the HTTP API, and give the reply.
+    async fn handle(&self, msg: &str) -> serde_json::Value {
+        let Request { id, op } = match serde_json::from_str(msg) {
+            Ok(req) => req,
+            Err(e) => {
+                warn!(eid = %self.eid, error = %e, "ignoring malformed socket request");
+                return reply(serde_json::Value::Null, Err(StatusCode::BAD_REQUEST));
+            }
+        };
+
+        let state = State(self.dynamo.clone());
+        let res = match op {
+            Op::Ask(q) => ask::ask(Path(self.eid), state, self.voter.clone(), Json(q))
+                .await
+                .map(|(_, json)| json),
+            Op::Vote { qid, direction } => {
+                crate::vote::vote(Path((self.eid, qid, direction)), state, self.voter.clone())
+                    .await
+                    .map(|(_, json)| json)
+            }
+            Op::Toggle {
+                qid,
+                property,
+                value,
+            } => match &self.secret {
+                Some(secret) => {
+                    let path = Path((self.eid, qid, property));
+                    crate::toggle::toggle(path, Secret(secret.clone()), state, value).await
+                }
+                None => {
+                    warn!(eid = %self.eid, %qid, "attempted to toggle from guest socket");
+                    Err(StatusCode::UNAUTHORIZED)
+                }
+            },
+        };
+        reply(id, res)
+    }
+}
+
+fn reply(
+    id: serde_json::Value,
+    res: Result<Json<serde_json::Value>, StatusCode>,
+) -> serde_json::Value {
+    match res {
+        Ok(Json(body)) => serde_json::json!({ "reply": id, "status": 200, "body": body }),
+        Err(status) => serde_json::json!({ "reply": id, "status": status.as_u16() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use tokio_tungstenite::tungstenite;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+
+        let app = axum::Router::new()
+            .route("/api/event/{eid}/socket", get(super::socket))
+            .with_state(backend.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let connect = async |secret: Option<&str>| {
+            use tungstenite::client::IntoClientRequest;
+            let mut req = format!("ws://{addr}/api/event/{eid}/socket")
+                .into_client_request()
+                .unwrap();
+            if let Some(secret) = secret {
+                req.headers_mut().insert(
+                    http::header::AUTHORIZATION,
+                    format!("Bearer {secret}").parse().unwrap(),
+                );
+            }
+            let (ws, res) = tokio_tungstenite::connect_async(req).await.unwrap();
+            assert!(res.headers().contains_key(http::header::SET_COOKIE));
+            ws
+        };
+        let mut guest = connect(None).await;
+        let mut host = connect(Some(secret)).await;
+
+        async fn send<S>(ws: &mut S, msg: Value)
+        where
+            S: SinkExt<tungstenite::Message> + Unpin,
+            S::Error: std::fmt::Debug,
+        {
+            ws.send(tungstenite::Message::text(msg.to_string()))
+                .await
+                .unwrap();
+        }
+        async fn next<S>(ws: &mut S) -> Value
+        where
+            S: StreamExt<Item = Result<tungstenite::Message, tungstenite::Error>> + Unpin,
+        {
+            let msg = ws.next().await.unwrap().unwrap();
+            serde_json::from_str(msg.to_text().unwrap()).unwrap()
+        }
+
+        // guests can ask, and everyone hears about it
+        send(
+            &mut guest,
+            json!({ "id": 1, "op": "ask", "body": "hello world", "asker": null }),
+        )
+        .await;
+        let reply = next(&mut guest).await;
+        assert_eq!(reply["reply"], 1);
+        assert_eq!(reply["status"], 200);
+        let qid = reply["body"]["id"].as_str().unwrap().to_string();
+        let asked = json!({ "event": "ask", "data": { "qid": qid, "pending": false } });
+        assert_eq!(next(&mut guest).await, asked);
+        assert_eq!(next(&mut host).await, asked);
+
+        // the asker already voted for their own question, but the host hasn't
+        send(
+            &mut guest,
+            json!({ "op": "vote", "qid": qid, "direction": "up" }),
+        )
+        .await;
+        assert_eq!(
+            next(&mut guest).await,
+            json!({ "reply": null, "status": 409 })
+        );
+        send(
+            &mut host,
+            json!({ "op": "vote", "qid": qid, "direction": "up" }),
+        )
+        .await;
+        let reply = next(&mut host).await;
+        assert_eq!(reply["body"]["votes"], 2);
+        let voted = json!({ "event": "vote", "data": { "qid": qid, "votes": 2 } });
+        assert_eq!(next(&mut host).await, voted);
+        assert_eq!(next(&mut guest).await, voted);
+
+        // only hosts can toggle
+        let hide = json!({ "op": "toggle", "qid": qid, "property": "hidden", "value": "on" });
+        send(&mut guest, hide.clone()).await;
+        assert_eq!(next(&mut guest).await["status"], 401);
+        send(&mut host, hide).await;
+        assert_eq!(next(&mut host).await["body"]["hidden"], true);
+        let hidden = json!({ "event": "hidden", "data": { "qid": qid, "hidden": true } });
+        assert_eq!(next(&mut host).await, hidden);
+        assert_eq!(next(&mut guest).await, hidden);
+
+        // and nonsense is refused
+        send(&mut guest, json!({ "op": "shout" })).await;
+        assert_eq!(next(&mut guest).await["status"], 400);
+
+        // as are questions larger than could be asked over HTTP
+        let long = "hello world ".repeat(ask::MAX_BODY / 12 + 1);
+        send(
+            &mut guest,
+            json!({ "op": "ask", "body": long, "asker": null }),
+        )
+        .await;
+        assert_eq!(next(&mut guest).await["status"], 413);
+        // and anything even larger than that closes the socket
+        let longer = "hello world ".repeat(MAX_MESSAGE / 12 + 1);
+        send(
+            &mut guest,
+            json!({ "op": "ask", "body": longer, "asker": null }),
+        )
+        .await;
+        assert!(matches!(
+            guest.next().await,
+            None | Some(Err(_) | Ok(tungstenite::Message::Close(_)))
+        ));
+
+        // sockets for events that don't exist, or with the wrong secret, are refused
+        let missing = "00000000000000000000000001";
+        let url = format!("ws://{addr}/api/event/{missing}/socket");
+        assert!(matches!(
+            tokio_tungstenite::connect_async(url).await,
+            Err(tungstenite::Error::Http(res)) if res.status() == StatusCode::NOT_FOUND
+        ));
+        use tungstenite::client::IntoClientRequest;
+        let mut req = format!("ws://{addr}/api/event/{eid}/socket")
+            .into_client_request()
+            .unwrap();
+        req.headers_mut()
+            .insert(http::header::AUTHORIZATION, "Bearer wrong".parse().unwrap());
+        assert!(matches!(
+            tokio_tungstenite::connect_async(req).await,
+            Err(tungstenite::Error::Http(res)) if res.status() == StatusCode::UNAUTHORIZED
+        ));
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
+}
@@ -0,0 +1,461 @@
+use crate::{ask, settings::Settings, toggle::ToggleRequest, vote::UpDown};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+use ulid::Ulid;
+
+mod dynamo;
+mod local;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub(crate) use dynamo::Dynamo;
+pub(crate) use local::Local;
+#[cfg(feature = "postgres")]
+pub(crate) use postgres::Postgres;
+#[cfg(feature = "sqlite")]
+pub(crate) use sqlite::Sqlite;
+
+/// An event as known to the store.
+#[derive(Clone)]
+pub(crate) struct Event {
+    /// As hashed by [`crate::utils::hash_secret`], or plaintext for older events.
+    pub secret: String,
+    pub settings: Settings,
+    /// Extra credentials that the host has handed out to co-hosts.
+    pub tokens: Vec<Token>,
+    /// How many times the settings have been changed, for [`Store::configure`] to check against.
+    pub revision: u64,
+}
+
+/// A named credential that the host has handed out, which works until it is revoked.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Token {
+    pub name: String,
+    /// Hashed like [`Event::secret`].
+    pub secret: String,
+    /// In seconds since the UNIX epoch.
+    pub created: u64,
+    #[serde(default)]
+    pub role: Role,
+    /// Whether a presenter also sees hidden questions.
+    #[serde(default)]
+    pub show_hidden: bool,
+}
+
+// neither of these print their secrets, so that they can't end up in logs by way of `?`
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Event")
+            .field("settings", &self.settings)
+            .field("tokens", &self.tokens)
+            .field("revision", &self.revision)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("name", &self.name)
+            .field("created", &self.created)
+            .field("role", &self.role)
+            .field("show_hidden", &self.show_hidden)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What a [`Token`] lets its holder do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Role {
+    /// Anything the host can do, except managing credentials.
+    #[default]
+    Host,
+    /// Only seeing the host's view of the questions, such as for projecting it on stage.
+    Presenter,
+}
+
+/// A question as it appears in an event's question list.
+///
+/// This only holds the parts of a question that change over time. The parts that don't are in
+/// [`QuestionDetails`] so that clients can cache them forever.
+#[derive(Debug, Clone)]
+pub(crate) struct ListedQuestion {
+    pub id: Ulid,
+    pub votes: usize,
+    pub hidden: bool,
+    /// Whether the question is still waiting for the host's approval.
+    pub pending: bool,
+    pub answered: Option<SystemTime>,
+    /// When the question was asked, or its votes, hidden, or answered state last changed.
+    pub modified: SystemTime,
+}
+
+/// The immutable parts of a question.
+///
+/// Along with them comes whether the question is currently hidden or pending, which decides who
+/// gets to see them.
+#[derive(Debug, Clone)]
+pub(crate) struct QuestionDetails {
+    pub id: Ulid,
+    pub eid: Ulid,
+    pub text: String,
+    pub when: SystemTime,
+    pub who: Option<String>,
+    pub hidden: bool,
+    pub pending: bool,
+}
+
+/// A question that was asked elsewhere, to be written as-is.
+#[derive(Debug, Clone)]
+pub(crate) struct ImportedQuestion {
+    pub id: Ulid,
+    pub text: String,
+    pub who: Option<String>,
+    /// When it was asked where it came from, which may be well before `id` was made.
+    pub when: SystemTime,
+    pub votes: usize,
+    pub hidden: bool,
+    pub answered: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// The event or question that the operation refers to does not exist.
+    NotFound,
+    /// The operation conflicts with what is already stored, such as a second up-vote by the same
+    /// voter.
+    Conflict,
+    /// The underlying store failed to carry out the operation.
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound => f.write_str("not found"),
+            Error::Conflict => f.write_str("conflict"),
+            Error::Backend(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NotFound | Error::Conflict => None,
+            Error::Backend(e) => Some(&**e),
+        }
+    }
+}
+
+/// When an event with `settings`, made at `now`, expires.
+///
+/// Handlers validate settings before they get to the store, so this only fails if that was skipped.
+fn event_expire(settings: &Settings, now: SystemTime) -> Result<SystemTime, Error> {
+    settings
+        .expire(now)
+        .ok_or_else(|| Error::Backend("event expires further out than can be represented".into()))
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        Error::Backend(Box::new(e))
+    }
+}
+
+/// Persistent storage for events and their questions.
+///
+/// Handlers only ever talk to the store through this trait (by way of [`crate::Backend`]), so
+/// adding a new kind of store is a matter of implementing it.
+#[async_trait::async_trait]
+pub(crate) trait Store: fmt::Debug + Send + Sync {
+    /// Register a new event with the given host secret and settings.
+    ///
+    /// Stores only ever see secrets that have already been hashed, and store them as they are.
+    ///
+    /// The event expires as given by [`Settings::expire`].
+    #[allow(clippy::wrong_self_convention)]
+    #[allow(clippy::new_ret_no_self)]
+    async fn new(&self, eid: &Ulid, secret: &str, settings: &Settings) -> Result<(), Error>;
+
+    /// Look up an event, if it exists.
+    async fn event(&self, eid: &Ulid) -> Result<Option<Event>, Error>;
+
+    /// List the events created at or after `since` that haven't expired yet, in no particular
+    /// order.
+    ///
+    /// This looks at every event, so it's only meant for occasional administrative use.
+    async fn events(&self, since: SystemTime) -> Result<Vec<Ulid>, Error>;
+
+    /// Replace the host secret of an event, as long as it is still `old` if that is given.
+    ///
+    /// Gives [`Error::Conflict`] if the secret changed since `old` was read.
+    async fn set_secret(&self, eid: &Ulid, old: Option<&str>, secret: &str) -> Result<(), Error>;
+
+    /// Replace the co-host tokens of an event, as long as they are still `old`.
+    ///
+    /// Gives [`Error::Conflict`] if the tokens changed since `old` was read, such as by a token
+    /// being minted or revoked at the same time, so that neither change gets lost.
+    async fn set_tokens(&self, eid: &Ulid, old: &[Token], tokens: &[Token]) -> Result<(), Error>;
+
+    /// Replace the settings of an event, and move when it expires if `expire` is given, as long as
+    /// the settings are still at `revision`.
+    ///
+    /// Gives [`Error::Conflict`] if the settings changed since `revision` was read, so that two
+    /// changes to different settings made at the same time don't undo one another.
+    async fn configure(
+        &self,
+        eid: &Ulid,
+        revision: u64,
+        settings: &Settings,
+        expire: Option<SystemTime>,
+    ) -> Result<(), Error>;
+
+    /// Add a question to an event.
+    ///
+    /// The question starts out with a single vote, which is recorded as coming from `voter`. If
+    /// `pending` is set, it is not shown to guests until the host approves it.
+    async fn ask(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        q: ask::Question,
+        pending: bool,
+        ttl: Duration,
+    ) -> Result<(), Error>;
+
+    /// Vote for a question of the event `eid` on behalf of `voter`, and return its new vote count.
+    ///
+    /// Each voter can only up-vote a question once, and only down-vote it after up-voting it.
+    /// Anything else gives [`Error::Conflict`]. Vote counts never go below zero. Gives
+    /// [`Error::NotFound`] if the question belongs to some other event.
+    async fn vote(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        direction: UpDown,
+    ) -> Result<usize, Error>;
+
+    /// Change whether a question of the event `eid` is hidden, answered, or approved.
+    ///
+    /// Gives [`Error::NotFound`] if the question belongs to some other event.
+    async fn toggle(&self, eid: &Ulid, qid: &Ulid, req: ToggleRequest) -> Result<(), Error>;
+
+    /// List the questions of an event, in no particular order.
+    ///
+    /// Hidden and pending questions are only included if `has_secret` is set.
+    async fn list(&self, eid: &Ulid, has_secret: bool) -> Result<Vec<ListedQuestion>, Error>;
+
+    /// Fetch the immutable parts of the given questions.
+    ///
+    /// Questions that do not exist are left out of the result.
+    async fn questions(&self, qids: &[Ulid]) -> Result<Vec<QuestionDetails>, Error>;
+
+    /// Make each of the event's questions expire `ttl` after it was asked.
+    ///
+    /// That is when it was asked in this event, as given by its id, and not its `when`: questions
+    /// imported from elsewhere keep their original `when`, but shouldn't expire any sooner than
+    /// questions asked at the time of the import.
+    async fn retain(&self, eid: &Ulid, ttl: Duration) -> Result<(), Error>;
+
+    /// Add many already-asked questions to an event at once, in batches.
+    ///
+    /// Each question expires `ttl` after its id was made, same as for [`Store::retain`], and none
+    /// of them need approval. Questions whose id is already taken are left as they are, so a
+    /// write that failed part-way can be retried with the same questions. Gives
+    /// [`Error::NotFound`] if the event doesn't exist.
+    async fn import(&self, eid: &Ulid, qs: &[ImportedQuestion], ttl: Duration)
+        -> Result<(), Error>;
+
+    /// Remove an event and all of its questions.
+    #[cfg(test)]
+    async fn delete(&self, eid: &Ulid);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{utils, Backend};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    async fn retain(backend: &Backend) {
+        let now = backend.now();
+        let eid = Ulid::from_datetime(now);
+        backend
+            .new(&eid, "secret", &Settings::default())
+            .await
+            .unwrap();
+        let qid = Ulid::from_datetime(now);
+        // as if imported from an event two months back
+        let when = now - 60 * DAY;
+        backend
+            .import(
+                &eid,
+                &[ImportedQuestion {
+                    id: qid,
+                    text: "asked long ago".into(),
+                    who: None,
+                    when,
+                    votes: 1,
+                    hidden: false,
+                    answered: None,
+                }],
+                DAY,
+            )
+            .await
+            .unwrap();
+
+        // retention counts from when the question was asked here, not from its `when`
+        backend.retain(&eid, 30 * DAY).await.unwrap();
+        assert_eq!(backend.list(&eid, true).await.unwrap().len(), 1);
+        let qs = backend.questions(&[qid]).await.unwrap();
+        assert_eq!(qs.len(), 1);
+        assert_eq!(
+            utils::to_unix_timestamp(qs[0].when),
+            utils::to_unix_timestamp(when)
+        );
+
+        // and with no retention at all, it's gone right away
+        backend.retain(&eid, Duration::ZERO).await.unwrap();
+        assert!(backend.list(&eid, true).await.unwrap().is_empty());
+        assert!(backend.questions(&[qid]).await.unwrap().is_empty());
+        // nor can it still be voted for or toggled
+        assert!(matches!(
+            backend
+                .vote(&eid, &qid, &Ulid::generate(), UpDown::Up)
+                .await,
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(
+            backend
+                .toggle(&eid, &qid, ToggleRequest::Hidden(true))
+                .await,
+            Err(Error::NotFound)
+        ));
+
+        backend.delete(&eid).await;
+    }
+
+    async fn set_tokens(backend: &Backend) {
+        let eid = Ulid::from_datetime(backend.now());
+        backend
+            .new(&eid, "secret", &Settings::default())
+            .await
+            .unwrap();
+        let token = |name: &str| Token {
+            name: name.into(),
+            secret: format!("{name}-secret"),
+            created: 0,
+            role: Role::Host,
+            show_hidden: false,
+        };
+        let ab = [token("a"), token("b")];
+        backend.set_tokens(&eid, &[], &ab[..1]).await.unwrap();
+
+        // a change made from what was read before `a` came along would lose `a`
+        assert!(matches!(
+            backend.set_tokens(&eid, &[], &ab[1..]).await,
+            Err(Error::Conflict)
+        ));
+        backend.set_tokens(&eid, &ab[..1], &ab).await.unwrap();
+        let event = backend.event(&eid).await.unwrap().unwrap();
+        assert!(event.tokens == ab);
+
+        // and back to none at all, which is also something to compare against
+        backend.set_tokens(&eid, &ab, &[]).await.unwrap();
+        backend.set_tokens(&eid, &[], &ab[1..]).await.unwrap();
+        assert!(matches!(
+            backend.set_tokens(&Ulid::generate(), &[], &ab).await,
+            Err(Error::NotFound)
+        ));
+
+        backend.delete(&eid).await;
+    }
+
+    async fn configure(backend: &Backend) {
+        let eid = Ulid::from_datetime(backend.now());
+        backend
+            .new(&eid, "secret", &Settings::default())
+            .await
+            .unwrap();
+        let event = backend.event(&eid).await.unwrap().unwrap();
+        assert_eq!(event.revision, 0);
+
+        let moderated = Settings {
+            moderated: true,
+            ..Default::default()
+        };
+        backend.configure(&eid, 0, &moderated, None).await.unwrap();
+        let event = backend.event(&eid).await.unwrap().unwrap();
+        assert_eq!(event.revision, 1);
+        assert!(event.settings.moderated);
+
+        // a change made from what was read before moderation was turned on would turn it off again
+        assert!(matches!(
+            backend.configure(&eid, 0, &Settings::default(), None).await,
+            Err(Error::Conflict)
+        ));
+        assert!(
+            backend
+                .event(&eid)
+                .await
+                .unwrap()
+                .unwrap()
+                .settings
+                .moderated
+        );
+        backend
+            .configure(&eid, 1, &Settings::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(backend.event(&eid).await.unwrap().unwrap().revision, 2);
+        assert!(matches!(
+            backend
+                .configure(&Ulid::generate(), 0, &moderated, None)
+                .await,
+            Err(Error::NotFound)
+        ));
+
+        backend.delete(&eid).await;
+    }
+
+    async fn inner(backend: Backend) {
+        retain(&backend).await;
+        set_tokens(&backend).await;
+        configure(&backend).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
+}
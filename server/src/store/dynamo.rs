@@ -0,0 +1,863 @@
+use super::{Error, Event, ImportedQuestion, ListedQuestion, QuestionDetails, Store, Token};
+use crate::{ask, clock::Clock, settings::Settings, toggle::ToggleRequest, utils, vote::UpDown};
+use aws_sdk_dynamodb::{
+    error::{BuildError, SdkError},
+    operation::{transact_write_items::TransactWriteItemsError, update_item::UpdateItemError},
+    types::{
+        AttributeValue, BatchStatementErrorCodeEnum, BatchStatementRequest, ConditionCheck, Delete,
+        KeysAndAttributes, Put, ReturnValuesOnConditionCheckFailure, TransactWriteItem, Update,
+    },
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// A store backed by the `events`, `questions`, and `votes` DynamoDB tables.
+///
+/// In production, every DynamoDB action used here has to be allowed by the Lambda's IAM policy in
+/// `infra/lambda.tf`, which neither DynamoDB Local nor the other stores check. That includes the
+/// less obvious ones: `ConditionCheckItem` for the transaction in `ask`, `DeleteItem` for taking
+/// back votes in `vote`, `PartiQLUpdate` for the batched statements in `retain`, and
+/// `PartiQLInsert` for those in `import`.
+#[derive(Debug, Clone)]
+pub(crate) struct Dynamo {
+    client: aws_sdk_dynamodb::Client,
+    clock: Clock,
+}
+
+impl Dynamo {
+    pub(crate) fn new(client: aws_sdk_dynamodb::Client, clock: Clock) -> Self {
+        Self { client, clock }
+    }
+
+    /// Run a batch of PartiQL statements, such as those made by `import` and `retain`.
+    ///
+    /// Each statement succeeds or fails on its own. Those that DynamoDB turned away because it was
+    /// busy are run again after a backoff, those that failed with one of the `expected` codes are
+    /// let be, and any other failure fails the whole batch.
+    async fn execute_batch(
+        &self,
+        eid: &Ulid,
+        mut statements: Vec<BatchStatementRequest>,
+        expected: &[BatchStatementErrorCodeEnum],
+    ) -> Result<(), Error> {
+        use BatchStatementErrorCodeEnum as Code;
+        for attempt in 1.. {
+            let r = self
+                .client
+                .batch_execute_statement()
+                .set_statements(Some(statements.clone()))
+                .send()
+                .await?;
+            // responses come in the same order as the statements
+            let mut again = Vec::new();
+            for (statement, response) in statements.into_iter().zip(r.responses()) {
+                let Some(e) = response.error() else {
+                    continue;
+                };
+                match e.code() {
+                    Some(code) if expected.contains(code) => {}
+                    Some(
+                        Code::ProvisionedThroughputExceeded
+                        | Code::RequestLimitExceeded
+                        | Code::ThrottlingError
+                        | Code::InternalServerError
+                        | Code::TransactionConflict,
+                    ) => again.push(statement),
+                    code => {
+                        error!(%eid, error = ?e, "batched statement failed");
+                        return Err(Error::Backend(
+                            format!("batched statement failed: {code:?}").into(),
+                        ));
+                    }
+                }
+            }
+            if again.is_empty() {
+                break;
+            }
+            if attempt == MAX_BATCH_ATTEMPTS {
+                error!(%eid, n = again.len(), "gave up on throttled statements");
+                return Err(Error::Backend(
+                    "DynamoDB kept turning away batched statements".into(),
+                ));
+            }
+            trace!(%eid, n = again.len(), attempt, "retrying throttled statements");
+            tokio::time::sleep(BATCH_BACKOFF * 2u32.pow(attempt - 1)).await;
+            statements = again;
+        }
+        Ok(())
+    }
+}
+
+impl<E, R> From<SdkError<E, R>> for Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+    R: std::fmt::Debug + Send + Sync + 'static,
+{
+    fn from(e: SdkError<E, R>) -> Self {
+        Error::Backend(Box::new(e))
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(e: BuildError) -> Self {
+        Error::Backend(Box::new(e))
+    }
+}
+
+/// How many times `questions` asks for the same keys, or `execute_batch` runs the same statements,
+/// before it gives up.
+const MAX_BATCH_ATTEMPTS: u32 = 8;
+
+/// How long to wait before first asking again, which doubles after each attempt.
+const BATCH_BACKOFF: Duration = Duration::from_millis(50);
+
+/// The key of the item in `votes` that records that `voter` voted for question `qid` of `eid`.
+fn vote_key(eid: &Ulid, qid: &Ulid, voter: &Ulid) -> AttributeValue {
+    AttributeValue::S(format!("{eid}#{qid}#{voter}"))
+}
+
+/// Whether a transaction was cancelled because the condition on its `i`th item didn't hold.
+fn failed_check(e: &TransactWriteItemsError, i: usize) -> bool {
+    matches!(
+        e,
+        TransactWriteItemsError::TransactionCanceledException(c)
+            if c.cancellation_reasons()
+                .get(i)
+                .is_some_and(|r| r.code() == Some("ConditionalCheckFailed"))
+    )
+}
+
+fn to_dynamo_timestamp(time: SystemTime) -> AttributeValue {
+    AttributeValue::N(utils::to_unix_timestamp(time).to_string())
+}
+
+fn from_dynamo_timestamp(v: &AttributeValue) -> Option<SystemTime> {
+    let secs = v.as_n().ok()?.parse::<u64>().ok()?;
+    utils::from_unix_timestamp(secs)
+}
+
+/// Whether an item has yet to expire, as TTL deletion can lag behind by days.
+fn live(item: &HashMap<String, AttributeValue>, now: SystemTime) -> bool {
+    item.get("expire")
+        .and_then(from_dynamo_timestamp)
+        .is_none_or(|expire| expire > now)
+}
+
+#[async_trait::async_trait]
+impl Store for Dynamo {
+    async fn new(&self, eid: &Ulid, secret: &str, settings: &Settings) -> Result<(), Error> {
+        let now = self.clock.now();
+        self.client
+            .put_item()
+            .table_name("events")
+            .item("id", AttributeValue::S(eid.to_string()))
+            .item("secret", AttributeValue::S(secret.to_string()))
+            .item("when", to_dynamo_timestamp(now))
+            .item(
+                "expire",
+                to_dynamo_timestamp(super::event_expire(settings, now)?),
+            )
+            .item(
+                "settings",
+                AttributeValue::S(serde_json::to_string(settings).expect("settings serialize")),
+            )
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn event(&self, eid: &Ulid) -> Result<Option<Event>, Error> {
+        let v = self
+            .client
+            .get_item()
+            .table_name("events")
+            .key("id", AttributeValue::S(eid.to_string()))
+            .projection_expression("secret,settings,tokens,revision,expire")
+            .send()
+            .await?;
+        let Some(item) = v.item() else {
+            return Ok(None);
+        };
+        // TTL deletion can lag behind by days, so an expired event may well still be here
+        if !live(item, self.clock.now()) {
+            return Ok(None);
+        }
+        // events from before settings were a thing don't have the attribute
+        let settings = match item.get("settings").and_then(|s| s.as_s().ok()) {
+            Some(settings) => serde_json::from_str(settings).map_err(|e| {
+                error!(%eid, error = %e, "found event with invalid settings");
+                Error::Backend(Box::new(e))
+            })?,
+            None => Settings::default(),
+        };
+        let tokens = match item.get("tokens").and_then(|s| s.as_s().ok()) {
+            Some(tokens) => serde_json::from_str(tokens).map_err(|e| {
+                error!(%eid, error = %e, "found event with invalid tokens");
+                Error::Backend(Box::new(e))
+            })?,
+            None => Vec::new(),
+        };
+        match item.get("secret").and_then(|s| s.as_s().ok()) {
+            Some(secret) => Ok(Some(Event {
+                secret: secret.clone(),
+                settings,
+                tokens,
+                // events whose settings were never changed don't have the attribute
+                revision: item
+                    .get("revision")
+                    .and_then(|v| v.as_n().ok())
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0),
+            })),
+            None => {
+                let attributes: Vec<_> = item.keys().collect();
+                error!(%eid, ?attributes, "found event without a string secret");
+                Err(Error::Backend("event without a string secret".into()))
+            }
+        }
+    }
+
+    async fn events(&self, since: SystemTime) -> Result<Vec<Ulid>, Error> {
+        // TTL deletion can lag behind by days, so also check that the events haven't expired yet
+        let items: Vec<_> = self
+            .client
+            .scan()
+            .table_name("events")
+            .filter_expression("#when >= :since AND expire > :now")
+            .projection_expression("id")
+            .expression_attribute_names("#when", "when")
+            .expression_attribute_values(":since", to_dynamo_timestamp(since))
+            .expression_attribute_values(":now", to_dynamo_timestamp(self.clock.now()))
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<_, _>>()
+            .await?;
+        items
+            .iter()
+            .map(|item| {
+                item.get("id")
+                    .and_then(|v| v.as_s().ok())
+                    .and_then(|v| Ulid::from_string(v).ok())
+                    .ok_or_else(|| {
+                        error!(?item, "found event without a valid id");
+                        Error::Backend("event without a valid id".into())
+                    })
+            })
+            .collect()
+    }
+
+    async fn set_secret(&self, eid: &Ulid, old: Option<&str>, secret: &str) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut upd = self
+            .client
+            .update_item()
+            .table_name("events")
+            .key("id", AttributeValue::S(eid.to_string()))
+            .update_expression("SET secret = :secret")
+            .expression_attribute_names("#expire", "expire")
+            .expression_attribute_values(":now", to_dynamo_timestamp(now))
+            .expression_attribute_values(":secret", AttributeValue::S(secret.to_string()))
+            // so we can tell a missing event apart from a secret that changed under us
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+        // the `attribute_exists` is so we don't create an event that only has `secret` if it
+        // doesn't exist
+        upd = match old {
+            Some(old) => upd
+                .condition_expression("attribute_exists(id) AND #expire > :now AND secret = :old")
+                .expression_attribute_values(":old", AttributeValue::S(old.to_string())),
+            None => upd.condition_expression("attribute_exists(id) AND #expire > :now"),
+        };
+        match upd.send().await {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                match e.into_err() {
+                    UpdateItemError::ConditionalCheckFailedException(e)
+                        if e.item().is_some_and(|e| live(e, now)) =>
+                    {
+                        Err(Error::Conflict)
+                    }
+                    _ => Err(Error::NotFound),
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn set_tokens(&self, eid: &Ulid, old: &[Token], tokens: &[Token]) -> Result<(), Error> {
+        let now = self.clock.now();
+        let tokens = serde_json::to_string(tokens).expect("tokens serialize");
+        // the tokens are only ever written by this, so `old` serializes to exactly what's stored,
+        // except that events that never had any tokens don't have the attribute at all
+        let unchanged = if old.is_empty() {
+            "(attribute_not_exists(tokens) OR tokens = :old)"
+        } else {
+            "tokens = :old"
+        };
+        let upd = self
+            .client
+            .update_item()
+            .table_name("events")
+            .key("id", AttributeValue::S(eid.to_string()))
+            .update_expression("SET tokens = :tokens")
+            .condition_expression(format!(
+                "attribute_exists(id) AND #expire > :now AND {unchanged}"
+            ))
+            .expression_attribute_names("#expire", "expire")
+            .expression_attribute_values(":now", to_dynamo_timestamp(now))
+            .expression_attribute_values(
+                ":old",
+                AttributeValue::S(serde_json::to_string(old).expect("tokens serialize")),
+            )
+            .expression_attribute_values(":tokens", AttributeValue::S(tokens))
+            // so we can tell a missing event apart from tokens that changed under us
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+        match upd.send().await {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                match e.into_err() {
+                    UpdateItemError::ConditionalCheckFailedException(e)
+                        if e.item().is_some_and(|e| live(e, now)) =>
+                    {
+                        Err(Error::Conflict)
+                    }
+                    _ => Err(Error::NotFound),
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn configure(
+        &self,
+        eid: &Ulid,
+        revision: u64,
+        settings: &Settings,
+        expire: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        let now = self.clock.now();
+        let settings = serde_json::to_string(settings).expect("settings serialize");
+        // events whose settings were never changed don't have a revision at all
+        let unchanged = if revision == 0 {
+            "(attribute_not_exists(revision) OR revision = :revision)"
+        } else {
+            "revision = :revision"
+        };
+        let mut upd = self
+            .client
+            .update_item()
+            .table_name("events")
+            .key("id", AttributeValue::S(eid.to_string()))
+            .update_expression("SET settings = :settings, revision = :next")
+            // so we don't create an event that only has `settings` if it doesn't exist
+            .condition_expression(format!("attribute_exists(id) AND {unchanged}"))
+            .expression_attribute_values(":settings", AttributeValue::S(settings))
+            .expression_attribute_values(":revision", AttributeValue::N(revision.to_string()))
+            .expression_attribute_values(":next", AttributeValue::N((revision + 1).to_string()))
+            // so we can tell a missing event apart from settings that changed under us
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+        if let Some(expire) = expire {
+            upd = upd
+                .update_expression("SET settings = :settings, revision = :next, expire = :expire")
+                .expression_attribute_values(":expire", to_dynamo_timestamp(expire));
+        }
+        match upd.send().await {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                match e.into_err() {
+                    UpdateItemError::ConditionalCheckFailedException(e)
+                        if e.item().is_some_and(|e| live(e, now)) =>
+                    {
+                        Err(Error::Conflict)
+                    }
+                    _ => Err(Error::NotFound),
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn ask(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        q: ask::Question,
+        pending: bool,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let now = self.clock.now();
+        // TTL deletion can lag behind by days, so also check that the event hasn't expired yet
+        let event = ConditionCheck::builder()
+            .table_name("events")
+            .key("id", AttributeValue::S(eid.to_string()))
+            .condition_expression("attribute_exists(id) AND #expire > :now")
+            .expression_attribute_names("#expire", "expire")
+            .expression_attribute_values(":now", to_dynamo_timestamp(now))
+            .build()?;
+        let mut question = Put::builder()
+            .table_name("questions")
+            .item("id", AttributeValue::S(qid.to_string()))
+            .item("eid", AttributeValue::S(eid.to_string()))
+            .item("votes", AttributeValue::N(1.to_string()))
+            .item("voters", AttributeValue::Ss(vec![voter.to_string()]))
+            .item("text", AttributeValue::S(q.body))
+            .item("when", to_dynamo_timestamp(now))
+            .item("expire", to_dynamo_timestamp(now + ttl))
+            .item("modified", to_dynamo_timestamp(now))
+            .item("hidden", AttributeValue::Bool(false))
+            .item("pending", AttributeValue::Bool(pending));
+        if let Some(asker) = q.asker {
+            question = question.item("who", AttributeValue::S(asker));
+        }
+        // retain can keep a question for up to MAX_TTL after it was asked, and its votes have to
+        // stick around for as long
+        let vote = Put::builder()
+            .table_name("votes")
+            .item("id", vote_key(eid, qid, voter))
+            .item("expire", to_dynamo_timestamp(now + *crate::MAX_TTL))
+            .build()?;
+
+        // the check and the writes happen atomically, so a question can't sneak in after the
+        // event is gone
+        let r = self
+            .client
+            .transact_write_items()
+            .transact_items(TransactWriteItem::builder().condition_check(event).build())
+            .transact_items(TransactWriteItem::builder().put(question.build()?).build())
+            .transact_items(TransactWriteItem::builder().put(vote).build())
+            .send()
+            .await;
+        match r {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(e))
+                if matches!(
+                    e.err(),
+                    TransactWriteItemsError::TransactionCanceledException(c)
+                        if c.cancellation_reasons()
+                            .iter()
+                            .any(|r| r.code() == Some("ConditionalCheckFailed"))
+                ) =>
+            {
+                Err(Error::NotFound)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn vote(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        direction: UpDown,
+    ) -> Result<usize, Error> {
+        let now = self.clock.now();
+        // each vote is an item of its own, since a question's voters would otherwise pile up on
+        // the question until it hits the item size limit. the vote and the count change together,
+        // and each only if its condition holds, so a repeated vote changes neither.
+        let vote = match direction {
+            UpDown::Up => TransactWriteItem::builder().put(
+                Put::builder()
+                    .table_name("votes")
+                    .item("id", vote_key(eid, qid, voter))
+                    .item("expire", to_dynamo_timestamp(now + *crate::MAX_TTL))
+                    .condition_expression("attribute_not_exists(id)")
+                    .build()?,
+            ),
+            UpDown::Down => TransactWriteItem::builder().delete(
+                Delete::builder()
+                    .table_name("votes")
+                    .key("id", vote_key(eid, qid, voter))
+                    .condition_expression("attribute_exists(id)")
+                    .build()?,
+            ),
+        };
+
+        // without the `eid` check, a vote for an unknown question would create it
+        let count = Update::builder()
+            .table_name("questions")
+            .key("id", AttributeValue::S(qid.to_string()))
+            .expression_attribute_values(":one", AttributeValue::N(1.to_string()))
+            .expression_attribute_values(":eid", AttributeValue::S(eid.to_string()))
+            .expression_attribute_names("#modified", "modified")
+            .expression_attribute_names("#expire", "expire")
+            .expression_attribute_values(":now", to_dynamo_timestamp(now));
+        let count = match direction {
+            UpDown::Up => count
+                .update_expression("SET votes = votes + :one, #modified = :now")
+                .condition_expression("eid = :eid AND #expire > :now"),
+            UpDown::Down => count
+                .update_expression("SET votes = votes - :one, #modified = :now")
+                .condition_expression("eid = :eid AND #expire > :now AND votes > :zero")
+                .expression_attribute_values(":zero", AttributeValue::N(0.to_string())),
+        };
+
+        let r = self
+            .client
+            .transact_write_items()
+            .transact_items(vote.build())
+            .transact_items(TransactWriteItem::builder().update(count.build()?).build())
+            .send()
+            .await;
+        match r {
+            Ok(_) => {}
+            // an unknown question can't have been voted for either, so check it first
+            Err(SdkError::ServiceError(e)) if failed_check(e.err(), 1) => {
+                return Err(Error::NotFound);
+            }
+            Err(SdkError::ServiceError(e)) if failed_check(e.err(), 0) => {
+                return Err(Error::Conflict);
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        // transactions don't hand back what they wrote, so read the new count back
+        let q = self
+            .client
+            .get_item()
+            .table_name("questions")
+            .key("id", AttributeValue::S(qid.to_string()))
+            .projection_expression("votes")
+            .consistent_read(true)
+            .send()
+            .await?;
+        q.item()
+            .and_then(|q| q.get("votes"))
+            .and_then(|v| v.as_n().ok())
+            .and_then(|v| v.parse::<usize>().ok())
+            .ok_or_else(|| {
+                error!(%qid, ?q, "found non-numeric vote count");
+                Error::Backend("non-numeric vote count".into())
+            })
+    }
+
+    async fn toggle(&self, eid: &Ulid, qid: &Ulid, req: ToggleRequest) -> Result<(), Error> {
+        let q = self
+            .client
+            .update_item()
+            .table_name("questions")
+            .key("id", AttributeValue::S(qid.to_string()))
+            // this also makes sure we don't create a question that only has `hidden`,
+            // `answered`, or `pending` if it doesn't exist
+            .condition_expression("eid = :eid AND #expire > :now")
+            .expression_attribute_values(":eid", AttributeValue::S(eid.to_string()))
+            .expression_attribute_names("#expire", "expire")
+            .expression_attribute_names("#modified", "modified")
+            .expression_attribute_values(":now", to_dynamo_timestamp(self.clock.now()));
+
+        let q = match req {
+            ToggleRequest::Hidden(set) => q
+                .update_expression("SET #field = :set, #modified = :now")
+                .expression_attribute_names("#field", "hidden")
+                .expression_attribute_values(":set", AttributeValue::Bool(set)),
+            ToggleRequest::Answered(time) => {
+                if let Some(time) = time {
+                    q.update_expression("SET #field = :set, #modified = :now")
+                        .expression_attribute_names("#field", "answered")
+                        .expression_attribute_values(":set", to_dynamo_timestamp(time))
+                } else {
+                    q.update_expression("SET #modified = :now REMOVE #field")
+                        .expression_attribute_names("#field", "answered")
+                }
+            }
+            ToggleRequest::Approved(set) => q
+                .update_expression("SET #field = :set, #modified = :now")
+                .expression_attribute_names("#field", "pending")
+                .expression_attribute_values(":set", AttributeValue::Bool(!set)),
+        };
+        match q.send().await {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                Err(Error::NotFound)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, eid: &Ulid, has_secret: bool) -> Result<Vec<ListedQuestion>, Error> {
+        let query = self
+            .client
+            .query()
+            .table_name("questions")
+            .index_name("top_v2")
+            .scan_index_forward(false)
+            .key_condition_expression("eid = :eid")
+            .expression_attribute_values(":eid", AttributeValue::S(eid.to_string()))
+            .expression_attribute_names("#expire", "expire")
+            .expression_attribute_values(":now", to_dynamo_timestamp(self.clock.now()));
+
+        // TTL deletion can lag behind by days, so skip expired questions
+        let live = "#expire > :now";
+        let query = if has_secret {
+            query.filter_expression(live)
+        } else {
+            query
+                // questions from before moderation was a thing don't have `pending`
+                .filter_expression(format!(
+                    "{live} AND #hidden = :false AND (attribute_not_exists(#pending) OR #pending = :false)"
+                ))
+                .expression_attribute_names("#hidden", "hidden".to_string())
+                .expression_attribute_names("#pending", "pending".to_string())
+                .expression_attribute_values(":false", AttributeValue::Bool(false))
+        };
+
+        // a single query only reads so much (1MB), which a large event's questions can go past
+        let items: Vec<_> = query
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<_, _>>()
+            .await?;
+        Ok(items
+            .iter()
+            .filter_map(|doc| {
+                // NB! If you are creating entries manually via the DynamoDB Web UI (or CLI)
+                // when developing and testing, make sure you are putting valid ulids as ids,
+                // since the db server will only check that `id` respects the `S` type, and so
+                // will not error back to you when you are saving a question (say, via Web UI)
+                // with `id="string-that-is-not-valid-ulid"`.
+                let qid = doc
+                    .get("id")
+                    .and_then(|v| v.as_s().ok())
+                    .and_then(|v| Ulid::from_string(v).ok());
+                let votes = doc
+                    .get("votes")
+                    .and_then(|v| v.as_n().ok())
+                    .and_then(|v| v.parse::<usize>().ok());
+                let hidden = doc.get("hidden").and_then(|v| v.as_bool().ok());
+                let pending = doc.get("pending").and_then(|v| v.as_bool().ok());
+                let answered = doc.get("answered").and_then(from_dynamo_timestamp);
+                let modified = doc.get("modified").and_then(from_dynamo_timestamp);
+                match (qid, votes, hidden) {
+                    (Some(id), Some(votes), Some(&hidden)) => Some(ListedQuestion {
+                        id,
+                        votes,
+                        hidden,
+                        pending: pending.copied().unwrap_or(false),
+                        answered,
+                        // questions from before this was tracked last changed when asked
+                        modified: modified.unwrap_or_else(|| id.datetime()),
+                    }),
+                    (Some(qid), _, _) => {
+                        error!(%eid, %qid, votes = ?doc.get("votes"), "found non-numeric vote count");
+                        None
+                    }
+                    _ => {
+                        error!(%eid, ?doc, "found non-ulid question id");
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+
+    async fn questions(&self, qids: &[Ulid]) -> Result<Vec<QuestionDetails>, Error> {
+        let keys = qids
+            .iter()
+            .map(|qid| {
+                HashMap::from_iter([(String::from("id"), AttributeValue::S(qid.to_string()))])
+            })
+            .collect();
+        let mut request = KeysAndAttributes::builder()
+            .set_keys(Some(keys))
+            .projection_expression("id,eid,#text,#when,who,expire,#hidden,#pending")
+            .expression_attribute_names("#text", "text")
+            .expression_attribute_names("#when", "when")
+            .expression_attribute_names("#hidden", "hidden")
+            .expression_attribute_names("#pending", "pending")
+            .build()
+            .expect("we're building correct things");
+        let mut t = Vec::new();
+        for attempt in 1.. {
+            let mut v = self
+                .client
+                .batch_get_item()
+                .request_items("questions", request)
+                .send()
+                .await?;
+            if let Some(mut r) = v.responses.take() {
+                t.extend(r.remove("questions").unwrap_or_default());
+            }
+            // DynamoDB only reads so much per request, and hands back the keys it didn't get to
+            // (with the projection and all), which it wants asked for again after a backoff
+            // https://docs.aws.amazon.com/amazondynamodb/latest/developerguide/Programming.Errors.html#Programming.Errors.BatchOperations
+            match v
+                .unprocessed_keys
+                .take()
+                .and_then(|mut u| u.remove("questions"))
+            {
+                Some(rest) if !rest.keys().is_empty() => {
+                    if attempt == MAX_BATCH_ATTEMPTS {
+                        error!(
+                            n = rest.keys().len(),
+                            "gave up on unprocessed question keys"
+                        );
+                        return Err(Error::Backend(
+                            "DynamoDB kept leaving question keys unprocessed".into(),
+                        ));
+                    }
+                    trace!(
+                        n = rest.keys().len(),
+                        attempt,
+                        "retrying unprocessed question keys"
+                    );
+                    tokio::time::sleep(BATCH_BACKOFF * 2u32.pow(attempt - 1)).await;
+                    request = rest;
+                }
+                _ => break,
+            }
+        }
+
+        let now = self.clock.now();
+        t.iter()
+            .filter(|q| live(q, now))
+            .map(|q| {
+                let qid = q
+                    .get("id")
+                    .and_then(|v| v.as_s().ok())
+                    .and_then(|v| Ulid::from_string(v).ok());
+                let eid = q
+                    .get("eid")
+                    .and_then(|v| v.as_s().ok())
+                    .and_then(|v| Ulid::from_string(v).ok());
+                let text = q.get("text").and_then(|v| v.as_s().ok());
+                let who = q.get("who").and_then(|v| v.as_s().ok());
+                let when = q.get("when").and_then(from_dynamo_timestamp);
+                let flag = |field| {
+                    q.get(field)
+                        .and_then(|v| v.as_bool().ok())
+                        .copied()
+                        .unwrap_or(false)
+                };
+                match (qid, eid, text, when) {
+                    (Some(id), Some(eid), Some(text), Some(when)) => Ok(QuestionDetails {
+                        id,
+                        eid,
+                        text: text.clone(),
+                        when,
+                        who: who.cloned(),
+                        hidden: flag("hidden"),
+                        // questions from before moderation was a thing don't have `pending`
+                        pending: flag("pending"),
+                    }),
+                    _ => {
+                        error!(?qids, ?q, "bad data types for id/eid/text/when");
+                        Err(Error::Backend("bad data types for id/eid/text/when".into()))
+                    }
+                }
+            })
+            .collect()
+    }
+
+    async fn retain(&self, eid: &Ulid, ttl: Duration) -> Result<(), Error> {
+        let qs = self.list(eid, true).await?;
+        // same batching as for `import`
+        for chunk in qs.chunks(25) {
+            let batch_update = chunk
+                .iter()
+                .map(|q| {
+                    // question ids are made from the time they were asked here, which saves
+                    // reading back `when` for each one
+                    BatchStatementRequest::builder()
+                        .statement(r#"UPDATE "questions" SET "expire"=? WHERE "id"=?"#)
+                        .parameters(to_dynamo_timestamp(q.id.datetime() + ttl)) // expire
+                        .parameters(AttributeValue::S(q.id.to_string())) // id
+                        .build()
+                        .expect("we're building correct things")
+                })
+                .collect::<Vec<_>>();
+            // questions that expired and were deleted in the meantime fail the `UPDATE`'s
+            // implied check that they exist, and they're gone either way
+            self.execute_batch(
+                eid,
+                batch_update,
+                &[BatchStatementErrorCodeEnum::ConditionalCheckFailed],
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn import(
+        &self,
+        eid: &Ulid,
+        qs: &[ImportedQuestion],
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let now = self.clock.now();
+        if self.event(eid).await?.is_none() {
+            return Err(Error::NotFound);
+        }
+        // DynamoDB supports batch operations using PartiQL syntax with `25` as max batch size
+        // https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_BatchExecuteStatement.html
+        for chunk in qs.chunks(25) {
+            let batch_insert = chunk
+                .iter()
+                .map(|q| {
+                    // imported votes have no voters, so nobody can take them back
+                    let mut item = vec![
+                        ("id", AttributeValue::S(q.id.to_string())),
+                        ("eid", AttributeValue::S(eid.to_string())),
+                        ("text", AttributeValue::S(q.text.clone())),
+                        ("when", to_dynamo_timestamp(q.when)),
+                        ("expire", to_dynamo_timestamp(q.id.datetime() + ttl)),
+                        ("votes", AttributeValue::N(q.votes.to_string())),
+                        ("hidden", AttributeValue::Bool(q.hidden)),
+                        ("pending", AttributeValue::Bool(false)),
+                        ("modified", to_dynamo_timestamp(now)),
+                    ];
+                    if let Some(who) = &q.who {
+                        item.push(("who", AttributeValue::S(who.clone())));
+                    }
+                    if let Some(answered) = q.answered {
+                        item.push(("answered", to_dynamo_timestamp(answered)));
+                    }
+                    let fields: Vec<_> = item.iter().map(|(k, _)| format!("'{k}': ?")).collect();
+                    BatchStatementRequest::builder()
+                        .statement(format!(
+                            r#"INSERT INTO "questions" VALUE {{{}}}"#,
+                            fields.join(", ")
+                        ))
+                        .set_parameters(Some(item.into_iter().map(|(_, v)| v).collect()))
+                        .build()
+                        .expect("we're building correct things")
+                })
+                .collect::<Vec<_>>();
+            // questions that are already there are from an earlier try at the same import
+            self.execute_batch(
+                eid,
+                batch_insert,
+                &[BatchStatementErrorCodeEnum::DuplicateItem],
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    async fn delete(&self, eid: &Ulid) {
+        for q in self.list(eid, true).await.unwrap() {
+            self.client
+                .delete_item()
+                .table_name("questions")
+                .key("id", AttributeValue::S(q.id.to_string()))
+                .send()
+                .await
+                .unwrap();
+        }
+        self.client
+            .delete_item()
+            .table_name("events")
+            .key("id", AttributeValue::S(eid.to_string()))
+            .send()
+            .await
+            .unwrap();
+    }
+}
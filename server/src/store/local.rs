@@ -0,0 +1,702 @@
+use super::{Error, Event, ImportedQuestion, ListedQuestion, QuestionDetails, Store, Token};
+use crate::{ask, clock::Clock, settings::Settings, toggle::ToggleRequest, vote::UpDown};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Question {
+    eid: Ulid,
+    text: String,
+    who: Option<String>,
+    when: SystemTime,
+    expire: SystemTime,
+    votes: usize,
+    hidden: bool,
+    #[serde(default)]
+    pending: bool,
+    answered: Option<SystemTime>,
+    /// Journals from before this was tracked just count everything as changed when loaded.
+    #[serde(default = "SystemTime::now")]
+    modified: SystemTime,
+    /// Everyone whose vote is currently counted in `votes`.
+    voters: HashSet<Ulid>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct EventRow {
+    secret: String,
+    expire: SystemTime,
+    #[serde(default)]
+    settings: Settings,
+    #[serde(default)]
+    tokens: Vec<Token>,
+    #[serde(default)]
+    revision: u64,
+}
+
+/// A single line of the on-disk journal.
+///
+/// Records hold the full new state of whatever they touch, so replaying the journal is just a
+/// matter of applying every record in order. The exception is votes, which only hold the one
+/// change, so that each vote doesn't write out the question's every voter again.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record {
+    Event {
+        id: Ulid,
+        event: EventRow,
+    },
+    Question {
+        id: Ulid,
+        question: Question,
+    },
+    Vote {
+        id: Ulid,
+        voter: Ulid,
+        direction: UpDown,
+        #[serde(default = "SystemTime::now")]
+        at: SystemTime,
+    },
+    Delete {
+        id: Ulid,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Tables {
+    events: HashMap<Ulid, EventRow>,
+    questions: HashMap<Ulid, Question>,
+    questions_by_eid: HashMap<Ulid, Vec<Ulid>>,
+    journal: Option<File>,
+}
+
+fn io_error(e: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Error::Backend(Box::new(e))
+}
+
+impl Tables {
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Event { id, event } => {
+                self.questions_by_eid.entry(id).or_default();
+                self.events.insert(id, event);
+            }
+            Record::Question { id, question } => {
+                let eid = question.eid;
+                if self.questions.insert(id, question).is_none() {
+                    self.questions_by_eid.entry(eid).or_default().push(id);
+                }
+            }
+            Record::Vote {
+                id,
+                voter,
+                direction,
+                at,
+            } => {
+                let Some(q) = self.questions.get_mut(&id) else {
+                    return;
+                };
+                q.modified = at;
+                match direction {
+                    UpDown::Up => {
+                        q.votes += 1;
+                        q.voters.insert(voter);
+                    }
+                    UpDown::Down => {
+                        q.votes = q.votes.saturating_sub(1);
+                        q.voters.remove(&voter);
+                    }
+                }
+            }
+            Record::Delete { id } => {
+                for qid in self.questions_by_eid.remove(&id).unwrap_or_default() {
+                    self.questions.remove(&qid);
+                }
+                self.events.remove(&id);
+            }
+        }
+    }
+
+    /// Write `record` to the journal (if any), and then apply it.
+    fn commit(&mut self, record: Record) -> Result<(), Error> {
+        if let Some(journal) = &mut self.journal {
+            let mut line = serde_json::to_vec(&record).map_err(io_error)?;
+            line.push(b'\n');
+            // a single write of the whole line, so a crash can at most truncate the last record
+            journal.write_all(&line).map_err(io_error)?;
+        }
+        self.apply(record);
+        Ok(())
+    }
+
+    /// Drop all events and questions whose TTL has passed.
+    ///
+    /// This is not journaled, since replaying the journal and then expiring gives the same result.
+    fn expire(&mut self, now: SystemTime) {
+        let Tables {
+            events,
+            questions,
+            questions_by_eid,
+            ..
+        } = self;
+        let nevents = events.len();
+        let nquestions = questions.len();
+        events.retain(|_, e| e.expire > now);
+        questions_by_eid.retain(|eid, qids| {
+            if !events.contains_key(eid) {
+                for qid in qids.iter() {
+                    questions.remove(qid);
+                }
+                return false;
+            }
+            qids.retain(|qid| {
+                let live = questions[qid].expire > now;
+                if !live {
+                    questions.remove(qid);
+                }
+                live
+            });
+            true
+        });
+        if events.len() != nevents || questions.len() != nquestions {
+            debug!(
+                questions = nquestions - questions.len(),
+                events = nevents - events.len(),
+                "removed expired entries"
+            );
+        }
+    }
+
+    fn event(&self, eid: &Ulid, now: SystemTime) -> Option<&EventRow> {
+        self.events.get(eid).filter(|e| e.expire > now)
+    }
+
+    fn question(&self, qid: &Ulid, now: SystemTime) -> Option<&Question> {
+        self.questions.get(qid).filter(|q| q.expire > now)
+    }
+
+    /// All the records needed to rebuild the current state from scratch.
+    fn snapshot(&self) -> impl Iterator<Item = Record> + '_ {
+        let events = self.events.iter().map(|(&id, event)| Record::Event {
+            id,
+            event: event.clone(),
+        });
+        let questions = self
+            .questions_by_eid
+            .values()
+            .flatten()
+            .map(|&id| Record::Question {
+                id,
+                question: self.questions[&id].clone(),
+            });
+        events.chain(questions)
+    }
+}
+
+/// An in-memory store, mostly useful for development and testing.
+///
+/// It can optionally be backed by a journal file (see [`Local::open`]) so that its contents
+/// survive restarts.
+///
+/// Expired events and questions are ignored on read, and cleared out whenever a new event is
+/// made, so that long-running servers don't grow without bound.
+#[derive(Debug)]
+pub(crate) struct Local {
+    tables: Mutex<Tables>,
+    clock: Clock,
+}
+
+impl Local {
+    /// An empty store that tells time using `clock`.
+    pub(crate) fn with_clock(clock: Clock) -> Self {
+        Self {
+            tables: Mutex::default(),
+            clock,
+        }
+    }
+
+    /// Load the store from the journal at `path`, and append all further changes to it.
+    ///
+    /// The journal is compacted into a snapshot of the current state every time it is opened, so
+    /// it only grows with the changes made since the last start.
+    pub(crate) fn open(path: impl AsRef<Path>, clock: Clock) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut tables = Tables::default();
+        match File::open(path) {
+            Ok(f) => {
+                let lines = BufReader::new(f)
+                    .lines()
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(io_error)?;
+                let n = lines.len();
+                for (i, line) in lines.into_iter().enumerate() {
+                    match serde_json::from_str(&line) {
+                        Ok(record) => tables.apply(record),
+                        Err(e) if i + 1 == n => {
+                            warn!(?path, error = %e, "ignoring truncated last journal record");
+                        }
+                        Err(e) => return Err(io_error(e)),
+                    }
+                }
+                info!(
+                    ?path,
+                    events = tables.events.len(),
+                    questions = tables.questions.len(),
+                    "loaded journal"
+                );
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_error(e)),
+        }
+        tables.expire(clock.now());
+
+        // compact by writing out a fresh snapshot, and then atomically swapping it in
+        let tmp = path.with_extension("tmp");
+        {
+            let mut w = BufWriter::new(File::create(&tmp).map_err(io_error)?);
+            for record in tables.snapshot() {
+                serde_json::to_writer(&mut w, &record).map_err(io_error)?;
+                w.write_all(b"\n").map_err(io_error)?;
+            }
+            w.into_inner()
+                .map_err(|e| io_error(e.into_error()))?
+                .sync_all()
+                .map_err(io_error)?;
+        }
+        fs::rename(&tmp, path).map_err(io_error)?;
+
+        tables.journal = Some(
+            OpenOptions::new()
+                .append(true)
+                .open(path)
+                .map_err(io_error)?,
+        );
+        Ok(Self {
+            tables: Mutex::new(tables),
+            clock,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Store for Local {
+    async fn new(&self, eid: &Ulid, secret: &str, settings: &Settings) -> Result<(), Error> {
+        let now = self.clock.now();
+        let expire = super::event_expire(settings, now)?;
+        let mut local = self.tables.lock().unwrap();
+        local.expire(now);
+        local.commit(Record::Event {
+            id: *eid,
+            event: EventRow {
+                secret: secret.to_string(),
+                expire,
+                settings: settings.clone(),
+                tokens: Vec::new(),
+                revision: 0,
+            },
+        })
+    }
+
+    async fn event(&self, eid: &Ulid) -> Result<Option<Event>, Error> {
+        let local = self.tables.lock().unwrap();
+        Ok(local.event(eid, self.clock.now()).map(|e| Event {
+            secret: e.secret.clone(),
+            settings: e.settings.clone(),
+            tokens: e.tokens.clone(),
+            revision: e.revision,
+        }))
+    }
+
+    async fn events(&self, since: SystemTime) -> Result<Vec<Ulid>, Error> {
+        let now = self.clock.now();
+        let local = self.tables.lock().unwrap();
+        // event ids are made from the time they were created
+        Ok(local
+            .events
+            .iter()
+            .filter(|(eid, e)| e.expire > now && eid.datetime() >= since)
+            .map(|(eid, _)| *eid)
+            .collect())
+    }
+
+    async fn set_secret(&self, eid: &Ulid, old: Option<&str>, secret: &str) -> Result<(), Error> {
+        let mut local = self.tables.lock().unwrap();
+        let mut event = local
+            .event(eid, self.clock.now())
+            .ok_or(Error::NotFound)?
+            .clone();
+        if old.is_some_and(|old| event.secret != old) {
+            return Err(Error::Conflict);
+        }
+        event.secret = secret.to_string();
+        local.commit(Record::Event { id: *eid, event })
+    }
+
+    async fn set_tokens(&self, eid: &Ulid, old: &[Token], tokens: &[Token]) -> Result<(), Error> {
+        let mut local = self.tables.lock().unwrap();
+        let mut event = local
+            .event(eid, self.clock.now())
+            .ok_or(Error::NotFound)?
+            .clone();
+        if event.tokens != old {
+            return Err(Error::Conflict);
+        }
+        event.tokens = tokens.to_vec();
+        local.commit(Record::Event { id: *eid, event })
+    }
+
+    async fn configure(
+        &self,
+        eid: &Ulid,
+        revision: u64,
+        settings: &Settings,
+        expire: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        let mut local = self.tables.lock().unwrap();
+        let mut event = local
+            .event(eid, self.clock.now())
+            .ok_or(Error::NotFound)?
+            .clone();
+        if event.revision != revision {
+            return Err(Error::Conflict);
+        }
+        event.settings = settings.clone();
+        event.revision += 1;
+        if let Some(expire) = expire {
+            event.expire = expire;
+        }
+        local.commit(Record::Event { id: *eid, event })
+    }
+
+    async fn ask(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        q: ask::Question,
+        pending: bool,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut local = self.tables.lock().unwrap();
+        if local.event(eid, now).is_none() {
+            return Err(Error::NotFound);
+        }
+
+        local.commit(Record::Question {
+            id: *qid,
+            question: Question {
+                eid: *eid,
+                text: q.body,
+                who: q.asker,
+                when: now,
+                expire: now + ttl,
+                votes: 1,
+                hidden: false,
+                pending,
+                answered: None,
+                modified: now,
+                voters: HashSet::from([*voter]),
+            },
+        })
+    }
+
+    async fn vote(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        direction: UpDown,
+    ) -> Result<usize, Error> {
+        let now = self.clock.now();
+        let mut local = self.tables.lock().unwrap();
+        let q = local
+            .question(qid, now)
+            .filter(|q| q.eid == *eid)
+            .ok_or(Error::NotFound)?;
+        let voted = q.voters.contains(voter);
+        match direction {
+            UpDown::Up if voted => return Err(Error::Conflict),
+            UpDown::Down if !voted => return Err(Error::Conflict),
+            _ => {}
+        }
+        local.commit(Record::Vote {
+            id: *qid,
+            voter: *voter,
+            direction,
+            at: now,
+        })?;
+        Ok(local.questions[qid].votes)
+    }
+
+    async fn toggle(&self, eid: &Ulid, qid: &Ulid, req: ToggleRequest) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut local = self.tables.lock().unwrap();
+        let mut q = local
+            .question(qid, now)
+            .filter(|q| q.eid == *eid)
+            .ok_or(Error::NotFound)?
+            .clone();
+        match req {
+            ToggleRequest::Hidden(set) => q.hidden = set,
+            ToggleRequest::Answered(time) => q.answered = time,
+            ToggleRequest::Approved(set) => q.pending = !set,
+        }
+        q.modified = now;
+        local.commit(Record::Question {
+            id: *qid,
+            question: q,
+        })
+    }
+
+    async fn list(&self, eid: &Ulid, has_secret: bool) -> Result<Vec<ListedQuestion>, Error> {
+        let now = self.clock.now();
+        let local = self.tables.lock().unwrap();
+        if local.event(eid, now).is_none() {
+            return Err(Error::NotFound);
+        }
+
+        Ok(local.questions_by_eid[eid]
+            .iter()
+            .map(|qid| (qid, &local.questions[qid]))
+            .filter(|(_, q)| q.expire > now)
+            .filter(|(_, q)| has_secret || !(q.hidden || q.pending))
+            .map(|(qid, q)| ListedQuestion {
+                id: *qid,
+                votes: q.votes,
+                hidden: q.hidden,
+                pending: q.pending,
+                answered: q.answered,
+                modified: q.modified,
+            })
+            .collect())
+    }
+
+    async fn questions(&self, qids: &[Ulid]) -> Result<Vec<QuestionDetails>, Error> {
+        let now = self.clock.now();
+        let local = self.tables.lock().unwrap();
+        Ok(qids
+            .iter()
+            .filter_map(|qid| {
+                let q = local.question(qid, now)?;
+                Some(QuestionDetails {
+                    id: *qid,
+                    eid: q.eid,
+                    text: q.text.clone(),
+                    when: q.when,
+                    who: q.who.clone(),
+                    hidden: q.hidden,
+                    pending: q.pending,
+                })
+            })
+            .collect())
+    }
+
+    async fn retain(&self, eid: &Ulid, ttl: Duration) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut local = self.tables.lock().unwrap();
+        if local.event(eid, now).is_none() {
+            return Err(Error::NotFound);
+        }
+        for qid in local.questions_by_eid[eid].clone() {
+            let Some(q) = local.question(&qid, now) else {
+                continue;
+            };
+            let mut q = q.clone();
+            q.expire = qid.datetime() + ttl;
+            local.commit(Record::Question {
+                id: qid,
+                question: q,
+            })?;
+        }
+        Ok(())
+    }
+
+    async fn import(
+        &self,
+        eid: &Ulid,
+        qs: &[ImportedQuestion],
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut local = self.tables.lock().unwrap();
+        if local.event(eid, now).is_none() {
+            return Err(Error::NotFound);
+        }
+        for q in qs {
+            if local.questions.contains_key(&q.id) {
+                continue;
+            }
+            // imported votes have no voters, so nobody can take them back
+            local.commit(Record::Question {
+                id: q.id,
+                question: Question {
+                    eid: *eid,
+                    text: q.text.clone(),
+                    who: q.who.clone(),
+                    when: q.when,
+                    expire: q.id.datetime() + ttl,
+                    votes: q.votes,
+                    hidden: q.hidden,
+                    pending: false,
+                    answered: q.answered,
+                    modified: now,
+                    voters: HashSet::new(),
+                },
+            })?;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    async fn delete(&self, eid: &Ulid) {
+        let mut local = self.tables.lock().unwrap();
+        assert!(local.events.contains_key(eid));
+        local.commit(Record::Delete { id: *eid }).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn reopen() {
+        let path = std::env::temp_dir().join(format!("wewerewondering-{}.jsonl", Ulid::generate()));
+        let eid = Ulid::generate();
+        let qid = Ulid::generate();
+        let deleted = Ulid::generate();
+        let voter = Ulid::generate();
+        {
+            let local = Local::open(&path, Clock::default()).unwrap();
+            local
+                .new(&eid, "secret", &Default::default())
+                .await
+                .unwrap();
+            local
+                .new(&deleted, "secret", &Default::default())
+                .await
+                .unwrap();
+            local
+                .ask(
+                    &eid,
+                    &qid,
+                    &Ulid::generate(),
+                    ask::Question {
+                        body: "hello world".into(),
+                        asker: Some("person".into()),
+                    },
+                    false,
+                    *crate::QUESTIONS_TTL,
+                )
+                .await
+                .unwrap();
+            local.vote(&eid, &qid, &voter, UpDown::Up).await.unwrap();
+            local
+                .toggle(&eid, &qid, ToggleRequest::Hidden(true))
+                .await
+                .unwrap();
+            local.delete(&deleted).await;
+        }
+
+        // twice, to also check that the compacted journal reads back correctly
+        for _ in 0..2 {
+            let local = Local::open(&path, Clock::default()).unwrap();
+            assert_eq!(local.event(&eid).await.unwrap().unwrap().secret, "secret");
+            assert!(local.event(&deleted).await.unwrap().is_none());
+            let qs = local.list(&eid, true).await.unwrap();
+            assert_eq!(qs.len(), 1);
+            assert_eq!(qs[0].id, qid);
+            assert_eq!(qs[0].votes, 2);
+            assert!(qs[0].hidden);
+            let qs = local.questions(&[qid]).await.unwrap();
+            assert_eq!(qs[0].text, "hello world");
+            assert_eq!(qs[0].who.as_deref(), Some("person"));
+            // voters are remembered too
+            assert!(matches!(
+                local.vote(&eid, &qid, &voter, UpDown::Up).await,
+                Err(Error::Conflict)
+            ));
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn expiry() {
+        let clock = Clock::fake(SystemTime::now());
+        let local = Local::with_clock(clock.clone());
+        let eid = Ulid::generate();
+        let qid = Ulid::generate();
+        let voter = Ulid::generate();
+        local
+            .new(&eid, "secret", &Default::default())
+            .await
+            .unwrap();
+        let ask = |qid| {
+            local.ask(
+                &eid,
+                qid,
+                &voter,
+                ask::Question {
+                    body: "hello world".into(),
+                    asker: None,
+                },
+                false,
+                *crate::QUESTIONS_TTL,
+            )
+        };
+        ask(&qid).await.unwrap();
+
+        clock.advance(*crate::QUESTIONS_TTL - Duration::from_secs(1));
+        assert_eq!(local.list(&eid, true).await.unwrap().len(), 1);
+        let fresh = Ulid::generate();
+        ask(&fresh).await.unwrap();
+
+        // the first question is now past its TTL, but the event and the later question are not
+        clock.advance(Duration::from_secs(1));
+        let qs = local.list(&eid, true).await.unwrap();
+        assert_eq!(qs.len(), 1);
+        assert_eq!(qs[0].id, fresh);
+        assert!(local.questions(&[qid]).await.unwrap().is_empty());
+        assert!(matches!(
+            local.vote(&eid, &qid, &voter, UpDown::Down).await,
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(
+            local.toggle(&eid, &qid, ToggleRequest::Hidden(true)).await,
+            Err(Error::NotFound)
+        ));
+
+        // making a new event clears out whatever has expired
+        local
+            .new(&Ulid::generate(), "secret", &Default::default())
+            .await
+            .unwrap();
+        assert!(!local.tables.lock().unwrap().questions.contains_key(&qid));
+
+        clock.advance(*crate::EVENTS_TTL - *crate::QUESTIONS_TTL);
+        assert!(local.event(&eid).await.unwrap().is_none());
+        assert!(matches!(local.list(&eid, true).await, Err(Error::NotFound)));
+        assert!(matches!(ask(&Ulid::generate()).await, Err(Error::NotFound)));
+        local
+            .new(&Ulid::generate(), "secret", &Default::default())
+            .await
+            .unwrap();
+        let tables = local.tables.lock().unwrap();
+        assert!(!tables.events.contains_key(&eid));
+        assert!(!tables.questions.contains_key(&fresh));
+    }
+}
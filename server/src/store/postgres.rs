@@ -0,0 +1,441 @@
+use super::{Error, Event, ImportedQuestion, ListedQuestion, QuestionDetails, Store, Token};
+use crate::{ask, clock::Clock, settings::Settings, toggle::ToggleRequest, utils, vote::UpDown};
+use sqlx::{
+    postgres::{PgPoolOptions, PgRow},
+    PgPool, QueryBuilder, Row,
+};
+use std::time::{Duration, SystemTime};
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// A store backed by a PostgreSQL database.
+///
+/// The schema mirrors the DynamoDB tables, and is created (or upgraded) by the migrations in
+/// `migrations/postgres` when the store is connected.
+#[derive(Debug, Clone)]
+pub(crate) struct Postgres {
+    pool: PgPool,
+    clock: Clock,
+}
+
+/// How many questions go into each `INSERT` of an import, which keeps well under the limit on
+/// bound parameters per statement.
+const IMPORT_BATCH: usize = 100;
+
+fn ts(time: SystemTime) -> i64 {
+    utils::to_unix_timestamp(time) as i64
+}
+
+fn parse_id(row: &PgRow, column: &str) -> Result<Ulid, Error> {
+    let id: String = row.try_get(column)?;
+    Ulid::from_string(&id).map_err(|e| Error::Backend(Box::new(e)))
+}
+
+fn parse_time(row: &PgRow, column: &str) -> Result<Option<SystemTime>, Error> {
+    let secs: Option<i64> = row.try_get(column)?;
+    secs.map(|secs| {
+        u64::try_from(secs)
+            .ok()
+            .and_then(utils::from_unix_timestamp)
+            .ok_or_else(|| Error::Backend(format!("{column} is out of range").into()))
+    })
+    .transpose()
+}
+
+impl Postgres {
+    /// Connect to the PostgreSQL database at `url`, and bring its schema up to date.
+    pub(crate) async fn connect(url: &str, clock: Clock) -> Result<Self, Error> {
+        let pool = PgPoolOptions::new().connect(url).await?;
+        // migrations take an advisory lock, so concurrent servers won't trip over each other
+        sqlx::migrate!("./migrations/postgres")
+            .run(&pool)
+            .await
+            .map_err(|e| Error::Backend(Box::new(e)))?;
+        Ok(Self { pool, clock })
+    }
+
+    /// Remove all events and questions whose TTL has passed.
+    ///
+    /// DynamoDB does this for us in the background; here we do it whenever a new event is made.
+    async fn expire(&self, now: SystemTime) -> Result<(), Error> {
+        let now = ts(now);
+        let mut tx = self.pool.begin().await?;
+        let questions = sqlx::query("DELETE FROM questions WHERE expire <= $1")
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        let events = sqlx::query("DELETE FROM events WHERE expire <= $1")
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        if questions.rows_affected() != 0 || events.rows_affected() != 0 {
+            debug!(
+                questions = questions.rows_affected(),
+                events = events.rows_affected(),
+                "removed expired rows"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Store for Postgres {
+    async fn new(&self, eid: &Ulid, secret: &str, settings: &Settings) -> Result<(), Error> {
+        let now = self.clock.now();
+        let expire = super::event_expire(settings, now)?;
+        self.expire(now).await?;
+        sqlx::query(
+            r#"INSERT INTO events (id, secret, "when", expire, settings) VALUES ($1, $2, $3, $4, $5)"#,
+        )
+            .bind(eid.to_string())
+            .bind(secret)
+            .bind(ts(now))
+            .bind(ts(expire))
+            .bind(serde_json::to_string(settings).expect("settings serialize"))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn event(&self, eid: &Ulid) -> Result<Option<Event>, Error> {
+        let row = sqlx::query(
+            "SELECT secret, settings, tokens, revision FROM events WHERE id = $1 AND expire > $2",
+        )
+        .bind(eid.to_string())
+        .bind(ts(self.clock.now()))
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            Some(row) => Ok(Some(Event {
+                secret: row.try_get("secret")?,
+                settings: serde_json::from_str(row.try_get("settings")?)
+                    .map_err(|e| Error::Backend(Box::new(e)))?,
+                tokens: serde_json::from_str(row.try_get("tokens")?)
+                    .map_err(|e| Error::Backend(Box::new(e)))?,
+                revision: row.try_get::<i64, _>("revision")? as u64,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn events(&self, since: SystemTime) -> Result<Vec<Ulid>, Error> {
+        sqlx::query(r#"SELECT id FROM events WHERE "when" >= $1 AND expire > $2"#)
+            .bind(ts(since))
+            .bind(ts(self.clock.now()))
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| {
+                Ulid::from_string(row.try_get("id")?).map_err(|e| Error::Backend(Box::new(e)))
+            })
+            .collect()
+    }
+
+    async fn set_secret(&self, eid: &Ulid, old: Option<&str>, secret: &str) -> Result<(), Error> {
+        let r = sqlx::query(
+            "UPDATE events SET secret = $1 WHERE id = $2 AND expire > $3 AND secret = COALESCE($4, secret)",
+        )
+        .bind(secret)
+        .bind(eid.to_string())
+        .bind(ts(self.clock.now()))
+        .bind(old)
+        .execute(&self.pool)
+        .await?;
+        if r.rows_affected() == 0 {
+            // either the event is gone, or someone else changed its secret first
+            return match self.event(eid).await? {
+                Some(_) => Err(Error::Conflict),
+                None => Err(Error::NotFound),
+            };
+        }
+        Ok(())
+    }
+
+    async fn set_tokens(&self, eid: &Ulid, old: &[Token], tokens: &[Token]) -> Result<(), Error> {
+        // the tokens are only ever written by this, so `old` serializes to exactly what's stored
+        let r = sqlx::query(
+            "UPDATE events SET tokens = $1 WHERE id = $2 AND expire > $3 AND tokens = $4",
+        )
+        .bind(serde_json::to_string(tokens).expect("tokens serialize"))
+        .bind(eid.to_string())
+        .bind(ts(self.clock.now()))
+        .bind(serde_json::to_string(old).expect("tokens serialize"))
+        .execute(&self.pool)
+        .await?;
+        if r.rows_affected() == 0 {
+            // either the event is gone, or someone else changed its tokens first
+            return match self.event(eid).await? {
+                Some(_) => Err(Error::Conflict),
+                None => Err(Error::NotFound),
+            };
+        }
+        Ok(())
+    }
+
+    async fn configure(
+        &self,
+        eid: &Ulid,
+        revision: u64,
+        settings: &Settings,
+        expire: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        let r = sqlx::query(
+            "UPDATE events SET settings = $1, expire = COALESCE($2, expire), revision = revision + 1
+             WHERE id = $3 AND expire > $4 AND revision = $5",
+        )
+        .bind(serde_json::to_string(settings).expect("settings serialize"))
+        .bind(expire.map(ts))
+        .bind(eid.to_string())
+        .bind(ts(self.clock.now()))
+        .bind(revision as i64)
+        .execute(&self.pool)
+        .await?;
+        if r.rows_affected() == 0 {
+            // either the event is gone, or someone else changed its settings first
+            return match self.event(eid).await? {
+                Some(_) => Err(Error::Conflict),
+                None => Err(Error::NotFound),
+            };
+        }
+        Ok(())
+    }
+
+    async fn ask(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        q: ask::Question,
+        pending: bool,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        // only insert if the event is (still) there, all in one statement
+        let r = sqlx::query(
+            r#"INSERT INTO questions (id, eid, text, who, "when", expire, votes, hidden, pending, modified)
+               SELECT $1, id, $2, $3, $4, $5, 1, FALSE, $8, $4 FROM events WHERE id = $6 AND expire > $7"#,
+        )
+        .bind(qid.to_string())
+        .bind(q.body)
+        .bind(q.asker)
+        .bind(ts(now))
+        .bind(ts(now + ttl))
+        .bind(eid.to_string())
+        .bind(ts(now))
+        .bind(pending)
+        .execute(&mut *tx)
+        .await?;
+        if r.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        sqlx::query("INSERT INTO voters (qid, voter) VALUES ($1, $2)")
+            .bind(qid.to_string())
+            .bind(voter.to_string())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn vote(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        direction: UpDown,
+    ) -> Result<usize, Error> {
+        // the UPDATE locks the question, so concurrent votes can't get lost, and the same voter
+        // can't sneak in a second vote while this one is in progress
+        let mut tx = self.pool.begin().await?;
+        let (count, record) = match direction {
+            UpDown::Up => (
+                "UPDATE questions SET votes = votes + 1, modified = $1 WHERE id = $2 AND eid = $3 AND expire > $1 RETURNING votes",
+                "INSERT INTO voters (qid, voter) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            ),
+            // never goes below zero, same as the `votes > :zero` condition for DynamoDB
+            UpDown::Down => (
+                "UPDATE questions SET votes = GREATEST(votes - 1, 0), modified = $1 WHERE id = $2 AND eid = $3 AND expire > $1 RETURNING votes",
+                "DELETE FROM voters WHERE qid = $1 AND voter = $2",
+            ),
+        };
+        let row = sqlx::query(count)
+            .bind(ts(self.clock.now()))
+            .bind(qid.to_string())
+            .bind(eid.to_string())
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(Error::NotFound)?;
+        let r = sqlx::query(record)
+            .bind(qid.to_string())
+            .bind(voter.to_string())
+            .execute(&mut *tx)
+            .await?;
+        if r.rows_affected() == 0 {
+            // dropping the transaction rolls back the vote count
+            return Err(Error::Conflict);
+        }
+        tx.commit().await?;
+        Ok(row.try_get::<i64, _>("votes")? as usize)
+    }
+
+    async fn toggle(&self, eid: &Ulid, qid: &Ulid, req: ToggleRequest) -> Result<(), Error> {
+        let query = match req {
+            ToggleRequest::Hidden(set) => sqlx::query(
+                "UPDATE questions SET hidden = $1, modified = $2 WHERE id = $3 AND eid = $4 AND expire > $2",
+            )
+            .bind(set),
+            ToggleRequest::Answered(time) => sqlx::query(
+                "UPDATE questions SET answered = $1, modified = $2 WHERE id = $3 AND eid = $4 AND expire > $2",
+            )
+            .bind(time.map(ts)),
+            ToggleRequest::Approved(set) => sqlx::query(
+                "UPDATE questions SET pending = $1, modified = $2 WHERE id = $3 AND eid = $4 AND expire > $2",
+            )
+            .bind(!set),
+        };
+        let r = query
+            .bind(ts(self.clock.now()))
+            .bind(qid.to_string())
+            .bind(eid.to_string())
+            .execute(&self.pool)
+            .await?;
+        if r.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn list(&self, eid: &Ulid, has_secret: bool) -> Result<Vec<ListedQuestion>, Error> {
+        // served from the `questions_top` index
+        sqlx::query(
+            "SELECT id, votes, hidden, pending, answered, modified FROM questions
+             WHERE eid = $1 AND expire > $2 AND ($3 OR NOT (hidden OR pending))
+             ORDER BY votes DESC",
+        )
+        .bind(eid.to_string())
+        .bind(ts(self.clock.now()))
+        .bind(has_secret)
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(ListedQuestion {
+                id: parse_id(row, "id")?,
+                votes: row.try_get::<i64, _>("votes")? as usize,
+                hidden: row.try_get("hidden")?,
+                pending: row.try_get("pending")?,
+                answered: parse_time(row, "answered")?,
+                modified: parse_time(row, "modified")?.ok_or(Error::Backend(
+                    "question without a modification time".into(),
+                ))?,
+            })
+        })
+        .collect()
+    }
+
+    async fn questions(&self, qids: &[Ulid]) -> Result<Vec<QuestionDetails>, Error> {
+        let qids: Vec<_> = qids.iter().map(Ulid::to_string).collect();
+        sqlx::query(
+            r#"SELECT id, eid, text, "when", who, hidden, pending FROM questions
+               WHERE id = ANY($1) AND expire > $2"#,
+        )
+        .bind(qids)
+        .bind(ts(self.clock.now()))
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(QuestionDetails {
+                id: parse_id(row, "id")?,
+                eid: parse_id(row, "eid")?,
+                text: row.try_get("text")?,
+                when: parse_time(row, "when")?
+                    .ok_or(Error::Backend("question without a creation time".into()))?,
+                who: row.try_get("who")?,
+                hidden: row.try_get("hidden")?,
+                pending: row.try_get("pending")?,
+            })
+        })
+        .collect()
+    }
+
+    async fn retain(&self, eid: &Ulid, ttl: Duration) -> Result<(), Error> {
+        // the time a question was asked here is in its id, which for imported questions is later
+        // than the `when` they were originally asked
+        let mut tx = self.pool.begin().await?;
+        let qids: Vec<String> =
+            sqlx::query_scalar("SELECT id FROM questions WHERE eid = $1 AND expire > $2")
+                .bind(eid.to_string())
+                .bind(ts(self.clock.now()))
+                .fetch_all(&mut *tx)
+                .await?;
+        for qid in qids {
+            let asked = Ulid::from_string(&qid)
+                .map_err(|e| Error::Backend(Box::new(e)))?
+                .datetime();
+            sqlx::query("UPDATE questions SET expire = $1 WHERE id = $2")
+                .bind(ts(asked + ttl))
+                .bind(qid)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn import(
+        &self,
+        eid: &Ulid,
+        qs: &[ImportedQuestion],
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let now = ts(self.clock.now());
+        let mut tx = self.pool.begin().await?;
+        let event = sqlx::query("SELECT 1 FROM events WHERE id = $1 AND expire > $2")
+            .bind(eid.to_string())
+            .bind(now)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if event.is_none() {
+            return Err(Error::NotFound);
+        }
+        for chunk in qs.chunks(IMPORT_BATCH) {
+            let mut query = QueryBuilder::new(
+                r#"INSERT INTO questions (id, eid, text, who, "when", expire, votes, hidden, pending, answered, modified) "#,
+            );
+            query.push_values(chunk, |mut row, q| {
+                row.push_bind(q.id.to_string())
+                    .push_bind(eid.to_string())
+                    .push_bind(q.text.clone())
+                    .push_bind(q.who.clone())
+                    .push_bind(ts(q.when))
+                    .push_bind(ts(q.id.datetime() + ttl))
+                    .push_bind(q.votes as i64)
+                    .push_bind(q.hidden)
+                    .push_bind(false)
+                    .push_bind(q.answered.map(ts))
+                    .push_bind(now);
+            });
+            // imported votes have no voters, so nobody can take them back
+            query.push(" ON CONFLICT (id) DO NOTHING");
+            query.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    async fn delete(&self, eid: &Ulid) {
+        // questions go along with the event through `ON DELETE CASCADE`
+        sqlx::query("DELETE FROM events WHERE id = $1")
+            .bind(eid.to_string())
+            .execute(&self.pool)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,472 @@
+use super::{Error, Event, ImportedQuestion, ListedQuestion, QuestionDetails, Store, Token};
+use crate::{ask, clock::Clock, settings::Settings, toggle::ToggleRequest, utils, vote::UpDown};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow},
+    QueryBuilder, Row, SqlitePool,
+};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// A store backed by a single SQLite database file.
+///
+/// The schema mirrors the DynamoDB tables, and is created (or upgraded) by the migrations in
+/// `migrations/sqlite` when the database is opened.
+#[derive(Debug, Clone)]
+pub(crate) struct Sqlite {
+    pool: SqlitePool,
+    clock: Clock,
+}
+
+/// How many questions go into each `INSERT` of an import, which keeps well under the limit on
+/// bound parameters per statement.
+const IMPORT_BATCH: usize = 100;
+
+fn ts(time: SystemTime) -> i64 {
+    utils::to_unix_timestamp(time) as i64
+}
+
+fn parse_id(row: &SqliteRow, column: &str) -> Result<Ulid, Error> {
+    let id: String = row.try_get(column)?;
+    Ulid::from_string(&id).map_err(|e| Error::Backend(Box::new(e)))
+}
+
+fn parse_time(row: &SqliteRow, column: &str) -> Result<Option<SystemTime>, Error> {
+    let secs: Option<i64> = row.try_get(column)?;
+    secs.map(|secs| {
+        u64::try_from(secs)
+            .ok()
+            .and_then(utils::from_unix_timestamp)
+            .ok_or_else(|| Error::Backend(format!("{column} is out of range").into()))
+    })
+    .transpose()
+}
+
+impl Sqlite {
+    /// Open (and create, if need be) the SQLite database at `path`.
+    ///
+    /// The special path `:memory:` gives a fresh in-memory database.
+    pub(crate) async fn open(path: impl AsRef<Path>, clock: Clock) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = if path == Path::new(":memory:") {
+            // every connection to :memory: gets its own database,
+            // so make sure there is only ever exactly one.
+            SqlitePoolOptions::new()
+                .min_connections(1)
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+        } else {
+            SqlitePoolOptions::new()
+        };
+        let pool = pool.connect_with(options).await?;
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&pool)
+            .await
+            .map_err(|e| Error::Backend(Box::new(e)))?;
+        Ok(Self { pool, clock })
+    }
+
+    /// Remove all events and questions whose TTL has passed.
+    ///
+    /// DynamoDB does this for us in the background; here we do it whenever a new event is made.
+    async fn expire(&self, now: SystemTime) -> Result<(), Error> {
+        let now = ts(now);
+        let mut tx = self.pool.begin().await?;
+        let questions = sqlx::query("DELETE FROM questions WHERE expire <= ?")
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        let events = sqlx::query("DELETE FROM events WHERE expire <= ?")
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        if questions.rows_affected() != 0 || events.rows_affected() != 0 {
+            debug!(
+                questions = questions.rows_affected(),
+                events = events.rows_affected(),
+                "removed expired rows"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Store for Sqlite {
+    async fn new(&self, eid: &Ulid, secret: &str, settings: &Settings) -> Result<(), Error> {
+        let now = self.clock.now();
+        let expire = super::event_expire(settings, now)?;
+        self.expire(now).await?;
+        sqlx::query(
+            r#"INSERT INTO events (id, secret, "when", expire, settings) VALUES (?, ?, ?, ?, ?)"#,
+        )
+        .bind(eid.to_string())
+        .bind(secret)
+        .bind(ts(now))
+        .bind(ts(expire))
+        .bind(serde_json::to_string(settings).expect("settings serialize"))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn event(&self, eid: &Ulid) -> Result<Option<Event>, Error> {
+        let row = sqlx::query(
+            "SELECT secret, settings, tokens, revision FROM events WHERE id = ? AND expire > ?",
+        )
+        .bind(eid.to_string())
+        .bind(ts(self.clock.now()))
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            Some(row) => Ok(Some(Event {
+                secret: row.try_get("secret")?,
+                settings: serde_json::from_str(row.try_get("settings")?)
+                    .map_err(|e| Error::Backend(Box::new(e)))?,
+                tokens: serde_json::from_str(row.try_get("tokens")?)
+                    .map_err(|e| Error::Backend(Box::new(e)))?,
+                revision: row.try_get::<i64, _>("revision")? as u64,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn events(&self, since: SystemTime) -> Result<Vec<Ulid>, Error> {
+        sqlx::query(r#"SELECT id FROM events WHERE "when" >= ? AND expire > ?"#)
+            .bind(ts(since))
+            .bind(ts(self.clock.now()))
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| {
+                Ulid::from_string(row.try_get("id")?).map_err(|e| Error::Backend(Box::new(e)))
+            })
+            .collect()
+    }
+
+    async fn set_secret(&self, eid: &Ulid, old: Option<&str>, secret: &str) -> Result<(), Error> {
+        let r = sqlx::query(
+            "UPDATE events SET secret = ? WHERE id = ? AND expire > ? AND secret = COALESCE(?, secret)",
+        )
+        .bind(secret)
+        .bind(eid.to_string())
+        .bind(ts(self.clock.now()))
+        .bind(old)
+        .execute(&self.pool)
+        .await?;
+        if r.rows_affected() == 0 {
+            // either the event is gone, or someone else changed its secret first
+            return match self.event(eid).await? {
+                Some(_) => Err(Error::Conflict),
+                None => Err(Error::NotFound),
+            };
+        }
+        Ok(())
+    }
+
+    async fn set_tokens(&self, eid: &Ulid, old: &[Token], tokens: &[Token]) -> Result<(), Error> {
+        // the tokens are only ever written by this, so `old` serializes to exactly what's stored
+        let r =
+            sqlx::query("UPDATE events SET tokens = ? WHERE id = ? AND expire > ? AND tokens = ?")
+                .bind(serde_json::to_string(tokens).expect("tokens serialize"))
+                .bind(eid.to_string())
+                .bind(ts(self.clock.now()))
+                .bind(serde_json::to_string(old).expect("tokens serialize"))
+                .execute(&self.pool)
+                .await?;
+        if r.rows_affected() == 0 {
+            // either the event is gone, or someone else changed its tokens first
+            return match self.event(eid).await? {
+                Some(_) => Err(Error::Conflict),
+                None => Err(Error::NotFound),
+            };
+        }
+        Ok(())
+    }
+
+    async fn configure(
+        &self,
+        eid: &Ulid,
+        revision: u64,
+        settings: &Settings,
+        expire: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        let r = sqlx::query(
+            "UPDATE events SET settings = ?, expire = COALESCE(?, expire), revision = revision + 1
+             WHERE id = ? AND expire > ? AND revision = ?",
+        )
+        .bind(serde_json::to_string(settings).expect("settings serialize"))
+        .bind(expire.map(ts))
+        .bind(eid.to_string())
+        .bind(ts(self.clock.now()))
+        .bind(revision as i64)
+        .execute(&self.pool)
+        .await?;
+        if r.rows_affected() == 0 {
+            // either the event is gone, or someone else changed its settings first
+            return match self.event(eid).await? {
+                Some(_) => Err(Error::Conflict),
+                None => Err(Error::NotFound),
+            };
+        }
+        Ok(())
+    }
+
+    async fn ask(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        q: ask::Question,
+        pending: bool,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        // only insert if the event is (still) there, all in one statement
+        let r = sqlx::query(
+            r#"INSERT INTO questions (id, eid, text, who, "when", expire, votes, hidden, pending, modified)
+               SELECT ?, id, ?, ?, ?, ?, 1, FALSE, ?, ? FROM events WHERE id = ? AND expire > ?"#,
+        )
+        .bind(qid.to_string())
+        .bind(q.body)
+        .bind(q.asker)
+        .bind(ts(now))
+        .bind(ts(now + ttl))
+        .bind(pending)
+        .bind(ts(now))
+        .bind(eid.to_string())
+        .bind(ts(now))
+        .execute(&mut *tx)
+        .await?;
+        if r.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        sqlx::query("INSERT INTO voters (qid, voter) VALUES (?, ?)")
+            .bind(qid.to_string())
+            .bind(voter.to_string())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn vote(
+        &self,
+        eid: &Ulid,
+        qid: &Ulid,
+        voter: &Ulid,
+        direction: UpDown,
+    ) -> Result<usize, Error> {
+        // the UPDATE locks the question, so concurrent votes can't get lost, and the same voter
+        // can't sneak in a second vote while this one is in progress
+        let mut tx = self.pool.begin().await?;
+        let (count, record) = match direction {
+            UpDown::Up => (
+                "UPDATE questions SET votes = votes + 1, modified = ? WHERE id = ? AND eid = ? AND expire > ? RETURNING votes",
+                "INSERT INTO voters (qid, voter) VALUES (?, ?) ON CONFLICT DO NOTHING",
+            ),
+            // never goes below zero, same as the `votes > :zero` condition for DynamoDB
+            UpDown::Down => (
+                "UPDATE questions SET votes = MAX(votes - 1, 0), modified = ? WHERE id = ? AND eid = ? AND expire > ? RETURNING votes",
+                "DELETE FROM voters WHERE qid = ? AND voter = ?",
+            ),
+        };
+        let now = ts(self.clock.now());
+        let row = sqlx::query(count)
+            .bind(now)
+            .bind(qid.to_string())
+            .bind(eid.to_string())
+            .bind(now)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(Error::NotFound)?;
+        let r = sqlx::query(record)
+            .bind(qid.to_string())
+            .bind(voter.to_string())
+            .execute(&mut *tx)
+            .await?;
+        if r.rows_affected() == 0 {
+            // dropping the transaction rolls back the vote count
+            return Err(Error::Conflict);
+        }
+        tx.commit().await?;
+        Ok(row.try_get::<i64, _>("votes")? as usize)
+    }
+
+    async fn toggle(&self, eid: &Ulid, qid: &Ulid, req: ToggleRequest) -> Result<(), Error> {
+        let query = match req {
+            ToggleRequest::Hidden(set) => sqlx::query(
+                "UPDATE questions SET hidden = ?, modified = ? WHERE id = ? AND eid = ? AND expire > ?",
+            )
+            .bind(set),
+            ToggleRequest::Answered(time) => sqlx::query(
+                "UPDATE questions SET answered = ?, modified = ? WHERE id = ? AND eid = ? AND expire > ?",
+            )
+            .bind(time.map(ts)),
+            ToggleRequest::Approved(set) => sqlx::query(
+                "UPDATE questions SET pending = ?, modified = ? WHERE id = ? AND eid = ? AND expire > ?",
+            )
+            .bind(!set),
+        };
+        let now = ts(self.clock.now());
+        let r = query
+            .bind(now)
+            .bind(qid.to_string())
+            .bind(eid.to_string())
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+        if r.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn list(&self, eid: &Ulid, has_secret: bool) -> Result<Vec<ListedQuestion>, Error> {
+        let query = if has_secret {
+            "SELECT id, votes, hidden, pending, answered, modified FROM questions WHERE eid = ? AND expire > ?"
+        } else {
+            "SELECT id, votes, hidden, pending, answered, modified FROM questions WHERE eid = ? AND expire > ? AND NOT (hidden OR pending)"
+        };
+        sqlx::query(query)
+            .bind(eid.to_string())
+            .bind(ts(self.clock.now()))
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(ListedQuestion {
+                    id: parse_id(row, "id")?,
+                    votes: row.try_get::<i64, _>("votes")? as usize,
+                    hidden: row.try_get("hidden")?,
+                    pending: row.try_get("pending")?,
+                    answered: parse_time(row, "answered")?,
+                    modified: parse_time(row, "modified")?.ok_or(Error::Backend(
+                        "question without a modification time".into(),
+                    ))?,
+                })
+            })
+            .collect()
+    }
+
+    async fn questions(&self, qids: &[Ulid]) -> Result<Vec<QuestionDetails>, Error> {
+        if qids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut query = QueryBuilder::new(
+            r#"SELECT id, eid, text, "when", who, hidden, pending FROM questions WHERE expire > "#,
+        );
+        query.push_bind(ts(self.clock.now()));
+        query.push(" AND id IN (");
+        let mut ids = query.separated(", ");
+        for qid in qids {
+            ids.push_bind(qid.to_string());
+        }
+        query.push(")");
+        query
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(QuestionDetails {
+                    id: parse_id(row, "id")?,
+                    eid: parse_id(row, "eid")?,
+                    text: row.try_get("text")?,
+                    when: parse_time(row, "when")?
+                        .ok_or(Error::Backend("question without a creation time".into()))?,
+                    who: row.try_get("who")?,
+                    hidden: row.try_get("hidden")?,
+                    pending: row.try_get("pending")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn retain(&self, eid: &Ulid, ttl: Duration) -> Result<(), Error> {
+        // the time a question was asked here is in its id, which for imported questions is later
+        // than the `when` they were originally asked
+        let mut tx = self.pool.begin().await?;
+        let qids: Vec<String> =
+            sqlx::query_scalar("SELECT id FROM questions WHERE eid = ? AND expire > ?")
+                .bind(eid.to_string())
+                .bind(ts(self.clock.now()))
+                .fetch_all(&mut *tx)
+                .await?;
+        for qid in qids {
+            let asked = Ulid::from_string(&qid)
+                .map_err(|e| Error::Backend(Box::new(e)))?
+                .datetime();
+            sqlx::query("UPDATE questions SET expire = ? WHERE id = ?")
+                .bind(ts(asked + ttl))
+                .bind(qid)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn import(
+        &self,
+        eid: &Ulid,
+        qs: &[ImportedQuestion],
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let now = ts(self.clock.now());
+        let mut tx = self.pool.begin().await?;
+        let event = sqlx::query("SELECT 1 FROM events WHERE id = ? AND expire > ?")
+            .bind(eid.to_string())
+            .bind(now)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if event.is_none() {
+            return Err(Error::NotFound);
+        }
+        for chunk in qs.chunks(IMPORT_BATCH) {
+            let mut query = QueryBuilder::new(
+                r#"INSERT INTO questions (id, eid, text, who, "when", expire, votes, hidden, pending, answered, modified) "#,
+            );
+            query.push_values(chunk, |mut row, q| {
+                row.push_bind(q.id.to_string())
+                    .push_bind(eid.to_string())
+                    .push_bind(q.text.clone())
+                    .push_bind(q.who.clone())
+                    .push_bind(ts(q.when))
+                    .push_bind(ts(q.id.datetime() + ttl))
+                    .push_bind(q.votes as i64)
+                    .push_bind(q.hidden)
+                    .push_bind(false)
+                    .push_bind(q.answered.map(ts))
+                    .push_bind(now);
+            });
+            // imported votes have no voters, so nobody can take them back
+            query.push(" ON CONFLICT (id) DO NOTHING");
+            query.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    async fn delete(&self, eid: &Ulid) {
+        // questions go along with the event through `ON DELETE CASCADE`
+        sqlx::query("DELETE FROM events WHERE id = ?")
+            .bind(eid.to_string())
+            .execute(&self.pool)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use super::Backend;
+use crate::ticket::{self, TicketQuery};
+use crate::utils::{self, Secret};
+use crate::{hub::Feed, store};
+use axum::extract::{Path, Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::stream::{self, Stream};
+use http::StatusCode;
+use std::convert::Infallible;
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Stream changes to an event's questions, including hidden and pending ones if a host secret is
+/// given, either directly or through a [ticket](crate::ticket).
+pub(super) async fn stream(
+    Path(eid): Path<Ulid>,
+    secret: Option<Secret>,
+    Query(query): Query<TicketQuery>,
+    State(dynamo): State<Backend>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    match ticket::secret(&dynamo, &eid, secret, query)? {
+        Some(secret) => {
+            utils::check_secret(&dynamo, &eid, &secret).await?;
+            stream_inner(eid, true, dynamo).await
+        }
+        None => {
+            utils::get_event(&dynamo, &eid).await?;
+            stream_inner(eid, false, dynamo).await
+        }
+    }
+}
+
+async fn stream_inner(
+    eid: Ulid,
+    has_secret: bool,
+    dynamo: Backend,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let feed = match Feed::new(&dynamo, eid, has_secret).await {
+        Ok(feed) => feed,
+        Err(store::Error::NotFound) => {
+            warn!(%eid, "request to stream non-existing event");
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "request to set up question stream failed");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    debug!(%eid, has_secret, "streaming questions");
+
+    // if the feed runs dry, the stream ends, and the client's EventSource reconnects and
+    // starts over from a fresh list
+    let events = stream::unfold(feed, |mut feed| async move {
+        let (kind, data) = feed.next().await?;
+        let event = Event::default().event(kind).data(data.to_string());
+        Some((Ok(event), feed))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::Property;
+    use crate::utils::Secret;
+    use crate::vote::{UpDown, Voter};
+    use axum::response::{IntoResponse, Json};
+    use http_body_util::BodyExt;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+
+        let mut guest = super::stream(Path(eid), None, Query::default(), State(backend.clone()))
+            .await
+            .unwrap()
+            .into_response()
+            .into_body();
+        let mut host = super::stream(
+            Path(eid),
+            Some(Secret(secret.to_string())),
+            Query::default(),
+            State(backend.clone()),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .into_body();
+        let next = async |body: &mut axum::body::Body| {
+            let frame = body.frame().await.unwrap().unwrap();
+            String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap()
+        };
+        let ask = async |body: &str| {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                Voter::generate(&backend),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .unwrap()
+            .1;
+            Ulid::from_string(q["id"].as_str().unwrap()).unwrap()
+        };
+
+        let qid = ask("hello world").await;
+        let asked = format!("event: ask\ndata: {{\"pending\":false,\"qid\":\"{qid}\"}}\n\n");
+        assert_eq!(next(&mut guest).await, asked);
+        assert_eq!(next(&mut host).await, asked);
+
+        let _ = crate::vote::vote(
+            Path((eid, qid, UpDown::Up)),
+            State(backend.clone()),
+            Voter::generate(&backend),
+        )
+        .await
+        .unwrap();
+        let voted = format!("event: vote\ndata: {{\"qid\":\"{qid}\",\"votes\":2}}\n\n");
+        assert_eq!(next(&mut guest).await, voted);
+        assert_eq!(next(&mut host).await, voted);
+
+        let _ = crate::toggle::toggle(
+            Path((eid, qid, Property::Hidden)),
+            Secret(secret.to_string()),
+            State(backend.clone()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+        let hidden = format!("event: hidden\ndata: {{\"hidden\":true,\"qid\":\"{qid}\"}}\n\n");
+        assert_eq!(next(&mut guest).await, hidden);
+        assert_eq!(next(&mut host).await, hidden);
+
+        // guests don't hear about votes for hidden questions, but hosts do
+        let _ = crate::vote::vote(
+            Path((eid, qid, UpDown::Up)),
+            State(backend.clone()),
+            Voter::generate(&backend),
+        )
+        .await
+        .unwrap();
+        let voted = format!("event: vote\ndata: {{\"qid\":\"{qid}\",\"votes\":3}}\n\n");
+        assert_eq!(next(&mut host).await, voted);
+        let other = ask("hello moon").await;
+        let asked = format!("event: ask\ndata: {{\"pending\":false,\"qid\":\"{other}\"}}\n\n");
+        assert_eq!(next(&mut guest).await, asked);
+        assert_eq!(next(&mut host).await, asked);
+
+        // nor do they hear about questions in other events
+        let e2 = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid2 = Ulid::from_string(e2["id"].as_str().unwrap()).unwrap();
+        let _ = crate::ask::ask(
+            Path(eid2),
+            State(backend.clone()),
+            Voter::generate(&backend),
+            Json(crate::ask::Question {
+                body: "hello sun".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let last = ask("hello stars").await;
+        let asked = format!("event: ask\ndata: {{\"pending\":false,\"qid\":\"{last}\"}}\n\n");
+        assert_eq!(next(&mut guest).await, asked);
+        backend.delete(&eid2).await;
+
+        // streams for events that don't exist, or with the wrong secret, are refused
+        let missing = Ulid::from_string("00000000000000000000000001").unwrap();
+        assert_eq!(
+            super::stream(
+                Path(missing),
+                None,
+                Query::default(),
+                State(backend.clone())
+            )
+            .await
+            .err(),
+            Some(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            super::stream(
+                Path(eid),
+                Some(Secret("wrong".into())),
+                Query::default(),
+                State(backend.clone())
+            )
+            .await
+            .err(),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
+}
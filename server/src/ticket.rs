@@ -0,0 +1,184 @@
+use super::Backend;
+use crate::utils::{self, Secret};
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use ulid::Ulid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How long after it was handed out a ticket can still be used.
+const TTL: Duration = Duration::from_secs(30);
+
+/// Tickets that have been handed out, but not used yet.
+///
+/// Browsers can't set headers on an `EventSource` or a WebSocket, so rather than putting a host
+/// secret in the path (from where it ends up in access logs), they trade the secret for a ticket
+/// and pass that in the query instead. A ticket only works once, for one event, and only briefly,
+/// so it's of no use to anyone who later finds it in a log.
+///
+/// Like the [`Hub`](crate::hub::Hub), this only reaches within a single process, which is all
+/// that the live routes it's for need.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Tickets(Arc<Mutex<HashMap<String, Ticket>>>);
+
+struct Ticket {
+    eid: Ulid,
+    secret: String,
+    expires: SystemTime,
+}
+
+// doesn't print the secret, same as for `store::Event`
+impl fmt::Debug for Ticket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ticket")
+            .field("eid", &self.eid)
+            .field("expires", &self.expires)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Tickets {
+    /// Hand out a ticket that stands in for `secret` when opening a live view of `eid`.
+    pub(crate) fn issue(&self, eid: Ulid, secret: String, now: SystemTime) -> String {
+        let ticket = crate::new::secret();
+        let mut tickets = self.0.lock().unwrap();
+        // tickets that never got used are cleared out whenever a new one is handed out
+        tickets.retain(|_, t| t.expires > now);
+        tickets.insert(
+            ticket.clone(),
+            Ticket {
+                eid,
+                secret,
+                expires: now + TTL,
+            },
+        );
+        ticket
+    }
+
+    /// Use up `ticket`, and give back the secret it stands in for if it's for `eid` and still
+    /// valid.
+    pub(crate) fn redeem(&self, eid: &Ulid, ticket: &str, now: SystemTime) -> Option<String> {
+        let t = self.0.lock().unwrap().remove(ticket)?;
+        (t.eid == *eid && t.expires > now).then_some(t.secret)
+    }
+}
+
+/// The query of the live routes, for clients that can't set the `Authorization` header.
+#[derive(Deserialize, Debug, Default)]
+pub(super) struct TicketQuery {
+    ticket: Option<String>,
+}
+
+/// The host secret a live view is opened with, if any.
+///
+/// That is the one in the `Authorization` header, or else the one that the ticket in the query
+/// was handed out for.
+pub(super) fn secret(
+    dynamo: &Backend,
+    eid: &Ulid,
+    header: Option<Secret>,
+    query: TicketQuery,
+) -> Result<Option<String>, StatusCode> {
+    match (header, query.ticket) {
+        (Some(Secret(secret)), _) => Ok(Some(secret)),
+        (None, Some(ticket)) => match dynamo.redeem_ticket(eid, &ticket) {
+            Some(secret) => Ok(Some(secret)),
+            None => {
+                warn!(%eid, "attempted to use unknown, used, or expired ticket");
+                Err(StatusCode::UNAUTHORIZED)
+            }
+        },
+        (None, None) => Ok(None),
+    }
+}
+
+/// Hand out a ticket for opening the event's live stream or socket with the given host secret.
+///
+/// The ticket goes in the `ticket` query parameter, and works once within the next 30 seconds.
+pub(super) async fn ticket(
+    Path(eid): Path<Ulid>,
+    Secret(secret): Secret,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    utils::check_secret(&dynamo, &eid, &secret).await?;
+    let ticket = dynamo.issue_ticket(eid, secret);
+    debug!(%eid, "handed out ticket");
+    Ok(Json(serde_json::json!({
+        "ticket": ticket,
+        "expires_in": TTL.as_secs(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use axum::extract::Query;
+
+    #[tokio::test]
+    async fn tickets() {
+        let clock = Clock::fake(SystemTime::now());
+        let backend = Backend::local_with_clock(clock.clone());
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let e2 = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid2 = Ulid::from_string(e2["id"].as_str().unwrap()).unwrap();
+
+        let issue = async |secret: &str| {
+            super::ticket(
+                Path(eid),
+                Secret(secret.to_string()),
+                State(backend.clone()),
+            )
+            .await
+            .map(|t| t["ticket"].as_str().unwrap().to_string())
+        };
+        let stream = async |eid, ticket: &str| {
+            crate::stream::stream(
+                Path(eid),
+                None,
+                Query(TicketQuery {
+                    ticket: Some(ticket.to_string()),
+                }),
+                State(backend.clone()),
+            )
+            .await
+            .map(|_| ())
+        };
+        assert_eq!(issue("wrong").await.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        // a ticket works once
+        let ticket = issue(secret).await.unwrap();
+        assert_eq!(stream(eid, &ticket).await, Ok(()));
+        assert_eq!(stream(eid, &ticket).await, Err(StatusCode::UNAUTHORIZED));
+
+        // for its own event only, and is used up by trying it on another
+        let ticket = issue(secret).await.unwrap();
+        assert_eq!(stream(eid2, &ticket).await, Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(stream(eid, &ticket).await, Err(StatusCode::UNAUTHORIZED));
+
+        // and only for a little while
+        let ticket = issue(secret).await.unwrap();
+        clock.advance(TTL);
+        assert_eq!(stream(eid, &ticket).await, Err(StatusCode::UNAUTHORIZED));
+
+        // it stands in for the secret it was handed out for, so it stops working along with it
+        let ticket = issue(secret).await.unwrap();
+        let _ = crate::credentials::rotate(
+            Path(eid),
+            Secret(secret.to_string()),
+            State(backend.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream(eid, &ticket).await, Err(StatusCode::UNAUTHORIZED));
+    }
+}
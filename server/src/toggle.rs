@@ -1,10 +1,7 @@
-use crate::utils;
-use crate::{Backend, Local};
-use aws_sdk_dynamodb::{
-    error::SdkError,
-    operation::update_item::{UpdateItemError, UpdateItemOutput},
-    types::AttributeValue,
-};
+use crate::hub::Update;
+use crate::store;
+use crate::utils::{self, Secret};
+use crate::Backend;
 use axum::{
     extract::{Path, State},
     Json,
@@ -22,74 +19,20 @@ use tracing::{debug, error, info, trace, warn};
 pub(super) enum Property {
     Hidden,
     Answered,
+    Approved,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub(super) enum ToggleRequest {
     Hidden(bool),
     Answered(Option<SystemTime>),
-}
-
-impl Backend {
-    pub(super) async fn toggle(
-        &self,
-        qid: &Ulid,
-        req: ToggleRequest,
-    ) -> Result<UpdateItemOutput, SdkError<UpdateItemError>> {
-        match self {
-            Self::Dynamo(dynamo) => {
-                let q = dynamo
-                    .update_item()
-                    .table_name("questions")
-                    .key("id", AttributeValue::S(qid.to_string()));
-
-                let q = match req {
-                    ToggleRequest::Hidden(set) => q
-                        .update_expression("SET #field = :set")
-                        .expression_attribute_names("#field", "hidden")
-                        .expression_attribute_values(":set", AttributeValue::Bool(set)),
-                    ToggleRequest::Answered(time) => {
-                        if let Some(time) = time {
-                            q.update_expression("SET #field = :set")
-                                .expression_attribute_names("#field", "answered")
-                                .expression_attribute_values(
-                                    ":set",
-                                    utils::to_dynamo_timestamp(time),
-                                )
-                        } else {
-                            q.update_expression("REMOVE #field")
-                                .expression_attribute_names("#field", "answered")
-                        }
-                    }
-                };
-                q.send().await
-            }
-            Self::Local(local) => {
-                let mut local = local.lock().unwrap();
-                let Local { questions, .. } = &mut *local;
-
-                let q = questions
-                    .get_mut(qid)
-                    .expect("toggle property on unknown question ");
-                match req {
-                    ToggleRequest::Hidden(set) => q.insert("hidden", AttributeValue::Bool(set)),
-                    ToggleRequest::Answered(time) => {
-                        if let Some(time) = time {
-                            q.insert("answered", utils::to_dynamo_timestamp(time))
-                        } else {
-                            q.remove("answered")
-                        }
-                    }
-                };
-
-                Ok(UpdateItemOutput::builder().build())
-            }
-        }
-    }
+    /// Approving a question of a moderated event shows it to guests.
+    Approved(bool),
 }
 
 pub(super) async fn toggle(
-    Path((eid, secret, qid, property)): Path<(Ulid, String, Ulid, Property)>,
+    Path((eid, qid, property)): Path<(Ulid, Ulid, Property)>,
+    Secret(secret): Secret,
     State(dynamo): State<Backend>,
     body: String,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
@@ -98,25 +41,26 @@ pub(super) async fn toggle(
     let req = match (&*body, property) {
         ("on", Property::Hidden) => ToggleRequest::Hidden(true),
         ("off", Property::Hidden) => ToggleRequest::Hidden(false),
-        ("on", Property::Answered) => ToggleRequest::Answered(Some(SystemTime::now())),
+        ("on", Property::Answered) => ToggleRequest::Answered(Some(dynamo.now())),
         ("off", Property::Answered) => ToggleRequest::Answered(None),
+        ("on", Property::Approved) => ToggleRequest::Approved(true),
+        ("off", Property::Approved) => ToggleRequest::Approved(false),
         _ => {
             error!(%qid, body, "invalid toggle value");
             return Err(http::StatusCode::BAD_REQUEST);
         }
     };
 
-    match dynamo.toggle(&qid, req).await {
+    match dynamo.toggle(&eid, &qid, req).await {
         Ok(_) => {
             debug!(%eid, %qid, p = ?property, "toggled question property");
+            dynamo.publish(Update::Toggled { eid, qid, req });
             match req {
                 ToggleRequest::Hidden(set) => Ok(Json(serde_json::json!({ "hidden": set }))),
+                ToggleRequest::Approved(set) => Ok(Json(serde_json::json!({ "approved": set }))),
                 ToggleRequest::Answered(time) => {
                     if let Some(time) = time {
-                        let time = time
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs();
+                        let time = utils::to_unix_timestamp(time);
                         Ok(Json(serde_json::json!({ "answered": time })))
                     } else {
                         Ok(Json(serde_json::json!({})))
@@ -124,8 +68,12 @@ pub(super) async fn toggle(
                 }
             }
         }
+        Err(store::Error::NotFound) => {
+            warn!(%eid, %qid, "attempted to toggle property of question not in event");
+            Err(http::StatusCode::NOT_FOUND)
+        }
         Err(e) => {
-            error!(%qid, error = %e, "dynamodb request to toggle question property failed");
+            error!(%qid, error = %e, "request to toggle question property failed");
             Err(http::StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -136,23 +84,26 @@ mod tests {
     use std::time::UNIX_EPOCH;
 
     use super::*;
+    use crate::utils::Secret;
     use axum::Json;
     use serde_json::Value;
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
         let secret = e["secret"].as_str().unwrap();
         let q = crate::ask::ask(
             Path(eid),
             State(backend.clone()),
+            crate::vote::Voter::generate(&backend),
             Json(crate::ask::Question {
                 body: "hello world".into(),
                 asker: None,
             }),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .1;
         let qid = q["id"].as_str().unwrap();
         let qid_u = Ulid::from_string(qid).unwrap();
 
@@ -203,7 +154,8 @@ mod tests {
 
         // only admin should see hidden
         let toggle_res = super::toggle(
-            Path((eid, secret.to_string(), qid_u, Property::Hidden)),
+            Path((eid, qid_u, Property::Hidden)),
+            Secret(secret.to_string()),
             State(backend.clone()),
             String::from("on"),
         )
@@ -214,25 +166,38 @@ mod tests {
             .expect("hidden should be a bool"));
 
         check(
-            crate::list::list_all(Path((eid, secret.to_string())), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                Some(Secret(secret.to_string())),
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0,
             Some((true, Box::new(check_answered_unset), 1)),
         );
         check(
-            crate::list::list(Path(eid), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                None,
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0,
             None,
         );
 
         // should toggle back
         let toggle_res = super::toggle(
-            Path((eid, secret.to_string(), qid_u, Property::Hidden)),
+            Path((eid, qid_u, Property::Hidden)),
+            Secret(secret.to_string()),
             State(backend.clone()),
             String::from("off"),
         )
@@ -244,7 +209,8 @@ mod tests {
 
         // and should now show up as answered
         let toggle_res = super::toggle(
-            Path((eid, secret.to_string(), qid_u, Property::Answered)),
+            Path((eid, qid_u, Property::Answered)),
+            Secret(secret.to_string()),
             State(backend.clone()),
             String::from("on"),
         )
@@ -253,25 +219,38 @@ mod tests {
         check_answered_set(&toggle_res);
 
         check(
-            crate::list::list_all(Path((eid, secret.to_string())), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                Some(Secret(secret.to_string())),
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0,
             Some((false, Box::new(check_answered_set), 1)),
         );
         check(
-            crate::list::list(Path(eid), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                None,
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0,
             Some((false, Box::new(check_answered_set), 1)),
         );
 
         // answered should toggle back
         let toggle_res = super::toggle(
-            Path((eid, secret.to_string(), qid_u, Property::Answered)),
+            Path((eid, qid_u, Property::Answered)),
+            Secret(secret.to_string()),
             State(backend.clone()),
             String::from("off"),
         )
@@ -280,22 +259,93 @@ mod tests {
         check_answered_unset(&toggle_res);
 
         check(
-            crate::list::list_all(Path((eid, secret.to_string())), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                Some(Secret(secret.to_string())),
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0,
             Some((false, Box::new(check_answered_unset), 1)),
         );
         check(
-            crate::list::list(Path(eid), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                None,
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0,
             Some((false, Box::new(check_answered_unset), 1)),
         );
 
+        // toggling a non-existing question gives 404, and doesn't create it
+        let unknown = Ulid::from_string("00000000000000000000000001").unwrap();
+        for property in [Property::Hidden, Property::Answered] {
+            assert_eq!(
+                super::toggle(
+                    Path((eid, unknown, property)),
+                    Secret(secret.to_string()),
+                    State(backend.clone()),
+                    String::from("on"),
+                )
+                .await
+                .unwrap_err(),
+                StatusCode::NOT_FOUND
+            );
+        }
+        assert!(backend.questions(&[unknown]).await.unwrap().is_empty());
+
+        // a host can't toggle questions of someone else's event
+        let other = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let other_eid = Ulid::from_string(other["id"].as_str().unwrap()).unwrap();
+        let other_secret = other["secret"].as_str().unwrap();
+        let other_q = crate::ask::ask(
+            Path(other_eid),
+            State(backend.clone()),
+            crate::vote::Voter::generate(&backend),
+            Json(crate::ask::Question {
+                body: "hello moon".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap()
+        .1;
+        let other_qid = Ulid::from_string(other_q["id"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            super::toggle(
+                Path((eid, other_qid, Property::Hidden)),
+                Secret(secret.to_string()),
+                State(backend.clone()),
+                String::from("on"),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        let other_qs = crate::list::list(
+            Path(other_eid),
+            Some(Secret(other_secret.to_string())),
+            Default::default(),
+            Default::default(),
+            State(backend.clone()),
+        )
+        .await
+        .1
+        .unwrap()
+        .0;
+        assert_eq!(other_qs[0]["hidden"], false);
+        backend.delete(&other_eid).await;
+
         backend.delete(&eid).await;
     }
 
@@ -304,9 +354,22 @@ mod tests {
         inner(Backend::local().await).await;
     }
 
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
     #[tokio::test]
     #[ignore]
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
 }
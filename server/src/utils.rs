@@ -1,212 +1,329 @@
-use crate::{Backend, Local};
-use aws_sdk_dynamodb::{error::SdkError, types::AttributeValue};
-use aws_smithy_types::body::SdkBody;
-use http::StatusCode;
-use std::time::SystemTime;
-use tracing::{error, warn};
+use crate::store::{Event, Role};
+use crate::Backend;
+use axum::extract::{FromRequestParts, OptionalFromRequestParts};
+use http::{header, request::Parts, Method, StatusCode};
+use rand::{rng, RngExt};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::ops::Range;
+use std::time::{Duration, SystemTime};
+use subtle::ConstantTimeEq;
+use tracing::{debug, error, warn};
 use ulid::Ulid;
 
-pub(crate) fn to_dynamo_timestamp(time: SystemTime) -> AttributeValue {
-    AttributeValue::N(
-        time.duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            .to_string(),
-    )
-}
-
-pub fn mint_service_error<E>(e: E) -> SdkError<E> {
-    SdkError::service_error(
-        e,
-        aws_smithy_runtime_api::http::Response::new(
-            aws_smithy_runtime_api::http::StatusCode::try_from(200).unwrap(),
-            SdkBody::empty(),
-        ),
-    )
-}
-
-pub async fn get_secret(dynamo: &Backend, eid: &Ulid) -> Result<String, StatusCode> {
-    match dynamo {
-        Backend::Dynamo(dynamo) => {
-            match dynamo
-                .get_item()
-                .table_name("events")
-                .key("id", AttributeValue::S(eid.to_string()))
-                .projection_expression("secret")
-                .send()
-                .await
-            {
-                Ok(v) => {
-                    if let Some(s) = v
-                        .item()
-                        .and_then(|e| e.get("secret"))
-                        .and_then(|s| s.as_s().ok())
-                    {
-                        Ok(s.clone())
-                    } else {
-                        warn!(%eid, "attempted to access non-existing event");
-                        Err(StatusCode::NOT_FOUND)
-                    }
-                }
-                Err(e) => {
-                    error!(%eid, error = %e, "dynamodb event request for secret verificaton failed");
-                    Err(http::StatusCode::INTERNAL_SERVER_ERROR)
-                }
+pub(crate) fn to_unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// The time `secs` seconds after the UNIX epoch, if that is one the system can represent.
+pub(crate) fn from_unix_timestamp(secs: u64) -> Option<SystemTime> {
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// A host credential, as given in an `Authorization: Bearer <secret>` header.
+///
+/// Handlers that take an `Option<Secret>` serve guests when there is no such header, but still
+/// reject malformed ones.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Secret(pub String);
+
+// so that secrets don't end up in logs by way of `?`
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl Secret {
+    fn from_headers(parts: &Parts) -> Option<Result<Self, StatusCode>> {
+        let value = parts.headers.get(header::AUTHORIZATION)?;
+        let secret = value
+            .to_str()
+            .ok()
+            .and_then(|v| v.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, secret)| secret.trim())
+            .filter(|secret| !secret.is_empty());
+        Some(match secret {
+            Some(secret) => Ok(Secret(secret.to_string())),
+            None => {
+                warn!("rejecting malformed authorization header");
+                Err(StatusCode::UNAUTHORIZED)
             }
+        })
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Secret {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Secret::from_headers(parts).unwrap_or_else(|| {
+            warn!(path = parts.uri.path(), "host request without credentials");
+            Err(StatusCode::UNAUTHORIZED)
+        })
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for Secret {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Option<Self>, Self::Rejection> {
+        Secret::from_headers(parts).transpose()
+    }
+}
+
+/// Where the secret is in `path`, if it is one of the old-style host routes that have the secret
+/// as a path segment, such as `/api/event/{eid}/questions/{secret}`.
+///
+/// The range includes the `/` in front of the secret, so that cutting it out leaves the path of
+/// the route that takes the secret in a header instead.
+pub(crate) fn secret_in_path(method: &Method, path: &str) -> Option<Range<usize>> {
+    let segments: Vec<_> = path.split('/').collect();
+    let ["", "api", "event", _, noun, secret, rest @ ..] = &segments[..] else {
+        return None;
+    };
+    let deprecated = match (*noun, rest) {
+        ("questions", []) | ("questions", [_, "toggle", _]) => true,
+        // the new-style route for revoking a token looks just like the old one for listing them
+        ("tokens", []) => method != Method::DELETE,
+        ("tokens", [_]) => true,
+        (
+            "present" | "moderate" | "export" | "import" | "settings" | "secret" | "stream"
+            | "socket",
+            [],
+        ) => true,
+        _ => false,
+    };
+    if !deprecated || secret.is_empty() {
+        return None;
+    }
+    let start: usize = segments[..5].iter().map(|s| s.len() + 1).sum::<usize>() - 1;
+    Some(start..start + 1 + secret.len())
+}
+
+/// What stored secrets start with once they are hashed.
+const HASHED: &str = "sha256$";
+
+/// Hash a host secret or token for storage, as `sha256$<salt>$<digest>`.
+///
+/// Secrets are random, not made up by people, so a fast hash is enough here; the salt only keeps
+/// the same secret from hashing the same way twice.
+pub(crate) fn hash_secret(secret: &str) -> String {
+    let salt = format!("{:032x}", rng().random::<u128>());
+    let digest = digest(&salt, secret);
+    format!("{HASHED}{salt}${digest}")
+}
+
+fn digest(salt: &str, secret: &str) -> String {
+    Sha256::new()
+        .chain_update(salt)
+        .chain_update(secret)
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Whether a stored secret is hashed, rather than the plaintext that events used to store.
+pub(crate) fn is_hashed(stored: &str) -> bool {
+    stored.starts_with(HASHED)
+}
+
+/// Check `secret` against a stored secret in constant time.
+///
+/// The stored secret may still be plaintext, for events from before secrets were hashed (see
+/// `admin hash-secrets`).
+pub(crate) fn verify_secret(stored: &str, secret: &str) -> bool {
+    match stored
+        .strip_prefix(HASHED)
+        .and_then(|hashed| hashed.split_once('$'))
+    {
+        Some((salt, expected)) => {
+            let digest = digest(salt, secret);
+            digest.as_bytes().ct_eq(expected.as_bytes()).into()
         }
-        Backend::Local(local) => {
-            let mut local = local.lock().unwrap();
-            let Local { events, .. } = &mut *local;
-            match events.get(eid) {
-                Some(s) => Ok(s.clone()),
-                None => Err(StatusCode::NOT_FOUND),
-            }
+        None => {
+            // hash both, so that the comparison doesn't give away the length either
+            let (stored, secret) = (digest("", stored), digest("", secret));
+            stored.as_bytes().ct_eq(secret.as_bytes()).into()
+        }
+    }
+}
+
+pub async fn get_event(dynamo: &Backend, eid: &Ulid) -> Result<Event, StatusCode> {
+    match dynamo.event(eid).await {
+        Ok(Some(e)) => Ok(e),
+        Ok(None) => {
+            warn!(%eid, "attempted to access non-existing event");
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "event request for secret verificaton failed");
+            Err(http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Which of an event's credentials a request was made with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Credential {
+    /// The event's own secret.
+    Secret,
+    /// A co-host token, by name.
+    CoHost(String),
+    /// A presenter token, by name.
+    Presenter { name: String, show_hidden: bool },
+}
+
+/// Check that `secret` is an active host credential of an event, and hand back the event if so.
+///
+/// That is either the event's secret or one of its co-host tokens. Presenter tokens get
+/// [`StatusCode::FORBIDDEN`], since they may only look.
+pub async fn check_secret(dynamo: &Backend, eid: &Ulid, secret: &str) -> Result<Event, StatusCode> {
+    match authenticate(dynamo, eid, secret).await? {
+        (e, Credential::Secret | Credential::CoHost(_)) => Ok(e),
+        (_, Credential::Presenter { name, .. }) => {
+            warn!(%eid, token = name, "presenter attempted host access");
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+}
+
+/// Check that `secret` is any of an event's credentials, including presenter tokens.
+///
+/// Hands back the event, and whether the credential may see hidden questions.
+pub(crate) async fn check_presenter(
+    dynamo: &Backend,
+    eid: &Ulid,
+    secret: &str,
+) -> Result<(Event, bool), StatusCode> {
+    match authenticate(dynamo, eid, secret).await? {
+        (e, Credential::Secret | Credential::CoHost(_)) => Ok((e, true)),
+        (e, Credential::Presenter { show_hidden, .. }) => Ok((e, show_hidden)),
+    }
+}
+
+/// Like [`check_secret`], but only accepts the event's own secret, and not co-host tokens.
+///
+/// This is for managing the credentials themselves, so that a co-host can't lock out the host.
+pub(crate) async fn check_host(
+    dynamo: &Backend,
+    eid: &Ulid,
+    secret: &str,
+) -> Result<Event, StatusCode> {
+    match authenticate(dynamo, eid, secret).await? {
+        (e, Credential::Secret) => Ok(e),
+        (_, Credential::CoHost(name) | Credential::Presenter { name, .. }) => {
+            warn!(%eid, token = name, "non-host attempted to manage credentials");
+            Err(StatusCode::FORBIDDEN)
         }
     }
 }
 
-pub async fn check_secret(dynamo: &Backend, eid: &Ulid, secret: &str) -> Result<(), StatusCode> {
-    let s = get_secret(dynamo, eid).await?;
-    if s == secret {
-        Ok(())
+/// Find which of an event's credentials `secret` is, and hand back the event along with it.
+pub(crate) async fn authenticate(
+    dynamo: &Backend,
+    eid: &Ulid,
+    secret: &str,
+) -> Result<(Event, Credential), StatusCode> {
+    let e = get_event(dynamo, eid).await?;
+    if !is_hashed(&e.secret) || e.tokens.iter().any(|t| !is_hashed(&t.secret)) {
+        debug!(%eid, "event still has plaintext credentials");
+    }
+    let credential = if verify_secret(&e.secret, secret) {
+        Some(Credential::Secret)
     } else {
-        warn!(%eid, secret, "attempted to access event with incorrect secret");
-        Err(StatusCode::UNAUTHORIZED)
+        e.tokens
+            .iter()
+            .find(|t| verify_secret(&t.secret, secret))
+            .map(|t| match t.role {
+                Role::Host => Credential::CoHost(t.name.clone()),
+                Role::Presenter => Credential::Presenter {
+                    name: t.name.clone(),
+                    show_hidden: t.show_hidden,
+                },
+            })
+    };
+    match credential {
+        Some(credential) => {
+            debug!(%eid, ?credential, "authenticated access to event");
+            Ok((e, credential))
+        }
+        None => {
+            warn!(%eid, "attempted to access event with incorrect secret");
+            Err(StatusCode::UNAUTHORIZED)
+        }
     }
 }
 
 /// Seed the database.
 ///
 /// This will register a test event (with id `00000000000000000000000000`) and
-/// a number of questions for it in the database, whether it's an in-memory [`Local`]
+/// a number of questions for it in the database, whether it's an in-memory [`Local`](crate::store::Local)
 /// database or a local instance of DynamoDB. Note that in the latter case
 /// we are checking if the test event is already there, and - if so - we are _not_ seeding
 /// the questions. This is to avoid creating duplicated questions when re-running the app.
-/// And this is not an issue of course when running against our in-memory [`Local`] database.
+/// And this is not an issue of course when running against our in-memory database.
 ///
-/// The returned vector contains IDs of the questions related to the test event.
+/// Returns the ID of the test event, and the IDs of its questions.
 #[cfg(debug_assertions)]
-pub(crate) async fn seed(backend: &mut Backend) -> Vec<Ulid> {
-    use crate::{ask, SEED};
-    use std::sync::{Arc, Mutex};
-    use tracing::{info, warn};
-
-    #[derive(serde::Deserialize)]
-    struct LiveAskQuestion {
-        likes: usize,
-        text: String,
-        hidden: bool,
-        answered: bool,
-        #[serde(rename = "createTimeUnix")]
-        created: usize,
-    }
+pub(crate) async fn seed(backend: &Backend) -> (Ulid, Vec<Ulid>) {
+    use crate::{import, SEED};
+    use tracing::info;
 
-    let seed: Vec<LiveAskQuestion> = serde_json::from_str(SEED).unwrap();
+    let seed = import::parse(import::Format::LiveAsk, SEED, backend.now()).unwrap();
     let seed_e = Ulid::from_string("00000000000000000000000000").unwrap();
     let seed_e_secret = "secret";
 
     info!("going to seed test event");
     match backend.event(&seed_e).await.unwrap() {
-        output if output.item().is_some() => {
+        Some(_) => {
             warn!("test event is already there, skipping seeding questions");
         }
-        _ => {
-            backend.new(&seed_e, seed_e_secret).await.unwrap();
+        None => {
+            backend
+                .new(&seed_e, &hash_secret(seed_e_secret), &Default::default())
+                .await
+                .unwrap();
             info!("successfully registered test event, going to seed questions now");
-            // first create questions ...
-            let mut qs = Vec::new();
-            for q in seed {
-                let qid = ulid::Ulid::generate();
-                backend
-                    .ask(
-                        &seed_e,
-                        &qid,
-                        ask::Question {
-                            body: q.text,
-                            asker: None,
-                        },
-                    )
-                    .await
-                    .unwrap();
-                qs.push((qid, q.created, q.likes, q.hidden, q.answered));
-            }
-            // ... then set the vote count + answered/hidden flags
-            match backend {
-                Backend::Dynamo(ref mut client) => {
-                    use aws_sdk_dynamodb::types::BatchStatementRequest;
-                    // DynamoDB supports batch operations using PartiQL syntax with `25` as max batch size
-                    // https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_BatchExecuteStatement.html
-                    for chunk in qs.chunks(25) {
-                        let batch_update = chunk
-                            .iter()
-                            .map(|(qid, created, votes, hidden, answered)| {
-                                let builder =  BatchStatementRequest::builder();
-                                let builder = if *answered {
-                                    builder.statement(
-                                       // numerous words are reserved in the DynamoDB engine (e.g. Key, Id, When) and
-                                       // should be qouted; we are quoting all of our attrs to avoid possible collisions
-                                       r#"UPDATE "questions" SET "answered"=? SET "votes"=? SET "when"=? SET "hidden"=? WHERE "id"=?"#,
-                                    )
-                                    .parameters(to_dynamo_timestamp(SystemTime::now())) // answered
-                                } else {
-                                    builder.statement(
-                                       r#"UPDATE "questions" SET "votes"=? SET "when"=? SET "hidden"=? WHERE "id"=?"#,
-                                    )
-                                };
-                                builder
-                                .parameters(AttributeValue::N(votes.to_string())) // votes
-                                .parameters(AttributeValue::N(created.to_string())) // when
-                                .parameters(AttributeValue::Bool(*hidden)) // hidden
-                                .parameters(AttributeValue::S(qid.to_string())) // id
-                                .build()
-                                .unwrap()
-                            })
-                            .collect::<Vec<_>>();
-                        client
-                            .batch_execute_statement()
-                            .set_statements(Some(batch_update))
-                            .send()
-                            .await
-                            .expect("batch to have been written ok");
-                    }
-                }
-                Backend::Local(ref mut state) => {
-                    let state = Arc::get_mut(state).unwrap();
-                    let state = Mutex::get_mut(state).unwrap();
-                    for (qid, created, votes, hidden, answered) in qs {
-                        let q = state.questions.get_mut(&qid).unwrap();
-                        q.insert("votes", AttributeValue::N(votes.to_string()));
-                        if answered {
-                            q.insert("answered", to_dynamo_timestamp(SystemTime::now()));
-                        }
-                        q.insert("hidden", AttributeValue::Bool(hidden));
-                        q.insert("when", AttributeValue::N(created.to_string()));
-                    }
-                }
-            }
+            import::load(backend, &seed_e, SEED, seed, *crate::QUESTIONS_TTL)
+                .await
+                .expect("batch to have been written ok");
             info!("successfully registered questions");
         }
     }
     // let's collect ids of the questions related to the test event,
     // we can then use them to auto-generate user votes over time
-    backend
+    let qids = backend
         .list(&seed_e, true)
         .await
         .expect("scenned index ok")
-        .items()
-        .iter()
-        .map(|item| {
-            let id = item
-                .get("id")
-                .expect("id is in projection")
-                .as_s()
-                .expect("id is of type string");
-            // NB! If you are creating entries manually via the DynamoDB Web UI (or CLI)
-            // when developing and testing, make sure you are putting valid ulids as ids,
-            // since the db server will only check that `id` respects the `S` type, and so
-            // will not error back to you when you are saving a question (say, via Web UI)
-            // with `id="string-that-is-not-valid-ulid"`.
-            ulid::Ulid::from_string(id).expect("all ids to be valid ulids in the table")
-        })
-        .collect()
+        .into_iter()
+        .map(|q| q.id)
+        .collect();
+    (seed_e, qids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashed_secrets() {
+        let hashed = hash_secret("secret");
+        assert!(is_hashed(&hashed));
+        assert!(verify_secret(&hashed, "secret"));
+        assert!(!verify_secret(&hashed, "secreT"));
+        assert!(!verify_secret(&hashed, ""));
+        // the same secret hashes differently every time
+        assert_ne!(hash_secret("secret"), hashed);
+
+        // plaintext from before secrets were hashed still works
+        assert!(!is_hashed("secret"));
+        assert!(verify_secret("secret", "secret"));
+        assert!(!verify_secret("secret", "secre"));
+    }
 }
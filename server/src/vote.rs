@@ -1,139 +1,201 @@
-use super::{Backend, Local};
-use aws_sdk_dynamodb::{
-    error::SdkError,
-    operation::update_item::{UpdateItemError, UpdateItemOutput},
-    types::{AttributeValue, ReturnValue},
-};
-use axum::extract::{Path, State};
-use axum::response::Json;
-use http::StatusCode;
-use serde::Deserialize;
+use super::Backend;
+use crate::{hub::Update, store, utils, EVENTS_TTL};
+use axum::extract::{FromRequestParts, Path, State};
+use axum::response::{IntoResponseParts, Json, ResponseParts};
+use http::{header, request::Parts, HeaderValue, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use ulid::Ulid;
 
 #[allow(unused_imports)]
 use tracing::{debug, error, info, trace, warn};
 
-#[derive(Deserialize, Debug, Copy, Clone)]
+#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
 pub(crate) enum UpDown {
     Up,
     Down,
 }
 
-impl Backend {
-    pub(crate) async fn vote(
-        &self,
-        qid: &Ulid,
-        direction: UpDown,
-    ) -> Result<UpdateItemOutput, SdkError<UpdateItemError>> {
-        match self {
-            Self::Dynamo(dynamo) => {
-                let upd = dynamo
-                    .update_item()
-                    .table_name("questions")
-                    .key("id", AttributeValue::S(qid.to_string()));
-
-                let upd = match direction {
-                    UpDown::Up => upd.update_expression("SET votes = votes + :one"),
-                    UpDown::Down => upd
-                        .update_expression("SET votes = votes - :one")
-                        .condition_expression("votes > :zero")
-                        .expression_attribute_values(":zero", AttributeValue::N(0.to_string())),
-                };
-                let upd = upd.expression_attribute_values(":one", AttributeValue::N(1.to_string()));
-
-                upd.return_values(ReturnValue::AllNew).send().await
-            }
-            Self::Local(local) => {
-                let mut local = local.lock().unwrap();
-                let Local { questions, .. } = &mut *local;
-
-                let ret = UpdateItemOutput::builder();
-                let q = questions
-                    .get_mut(qid)
-                    .expect("voting for non-existing question");
-                if let Some(AttributeValue::N(n)) = q.get_mut("votes") {
-                    let real_n = n.parse::<usize>().expect("votes values are numbers");
-                    match direction {
-                        UpDown::Up => {
-                            *n = (real_n + 1).to_string();
-                        }
-                        UpDown::Down => {
-                            if real_n > 0 {
-                                *n = (real_n - 1).to_string();
-                            }
-                        }
-                    };
-                } else {
-                    unreachable!("no votes for question");
-                }
-                let ret = ret.set_attributes(Some(
-                    q.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
-                ));
-                Ok(ret.build())
+const VOTER_COOKIE: &str = "voter";
+
+/// The token that tells one voter apart from another.
+///
+/// Clients get one (as the `voter` cookie) the first time they ask or vote, and the store uses it
+/// to make sure that each client only votes for a question once. The cookie is the voter's ULID
+/// along with the server's signature of it, since otherwise anyone could vote again and again
+/// just by making up new ULIDs. Clients that present no token, or one that isn't signed, are
+/// handed a new one.
+#[derive(Debug, Clone)]
+pub(crate) struct Voter {
+    pub(crate) id: Ulid,
+    /// The `Set-Cookie` value that hands the token to the client, if it doesn't have it yet.
+    cookie: Option<HeaderValue>,
+}
+
+impl Voter {
+    /// A new voter token, which will be handed to the client in the response.
+    pub(crate) fn generate(dynamo: &Backend) -> Self {
+        let id = Ulid::generate();
+        let signature = dynamo.sign(&[VOTER_COOKIE, &id.to_string()]);
+        // no event lives longer than this, so neither do the votes that the token guards
+        let cookie = format!(
+            "{VOTER_COOKIE}={id}.{signature}; \
+             Path=/api; Max-Age={}; HttpOnly; Secure; SameSite=Strict",
+            EVENTS_TTL.as_secs()
+        );
+        Voter {
+            id,
+            cookie: Some(
+                HeaderValue::from_str(&cookie).expect("ULIDs and hex are valid header values"),
+            ),
+        }
+    }
+}
+
+impl FromRequestParts<Backend> for Voter {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        dynamo: &Backend,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(token) = parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|c| c.trim().split_once('='))
+            .find_map(|(name, value)| (name == VOTER_COOKIE).then_some(value))
+        else {
+            return Ok(Voter::generate(dynamo));
+        };
+        let id = token
+            .split_once('.')
+            .and_then(|(id, signature)| Some((id.parse::<Ulid>().ok()?, signature)))
+            .filter(|(id, signature)| dynamo.verify(&[VOTER_COOKIE, &id.to_string()], signature));
+        Ok(match id {
+            Some((id, _)) => Voter { id, cookie: None },
+            None => {
+                // this includes the unsigned tokens handed out before they were signed
+                warn!(%token, "replacing unsigned or forged voter token");
+                Voter::generate(dynamo)
             }
+        })
+    }
+}
+
+impl IntoResponseParts for Voter {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Some(cookie) = self.cookie {
+            res.headers_mut().append(header::SET_COOKIE, cookie);
         }
+        Ok(res)
     }
 }
 
+/// Vote for a question of the event `eid`.
+///
+/// This reads the event, to check that it's taking votes, and then counts the vote in a single
+/// write that also checks that the question belongs to the event.
 pub(super) async fn vote(
-    Path((qid, direction)): Path<(Ulid, UpDown)>,
+    Path((eid, qid, direction)): Path<(Ulid, Ulid, UpDown)>,
     State(dynamo): State<Backend>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    match dynamo.vote(&qid, direction).await {
-        Ok(v) => {
+    voter: Voter,
+) -> Result<(Voter, Json<serde_json::Value>), StatusCode> {
+    let event = utils::get_event(&dynamo, &eid).await?;
+    let phase = event.settings.phase(dynamo.now());
+    if !phase.takes_votes() {
+        warn!(%eid, %qid, ?phase, "rejecting vote for event that isn't open");
+        return Err(http::StatusCode::FORBIDDEN);
+    }
+
+    match dynamo.vote(&eid, &qid, &voter.id, direction).await {
+        Ok(new_count) => {
             debug!(%qid, "voted for question");
-            let new_count = v
-                .attributes()
-                .and_then(|a| a.get("votes"))
-                .and_then(|v| v.as_n().ok())
-                .and_then(|v| v.parse::<usize>().ok());
-            Ok(Json(serde_json::json!({ "votes": new_count })))
+            dynamo.publish(Update::Voted {
+                qid,
+                votes: new_count,
+            });
+            Ok((voter, Json(serde_json::json!({ "votes": new_count }))))
         }
-        Err(ref error @ SdkError::ServiceError(ref e)) => {
-            if e.err().is_conditional_check_failed_exception() {
-                Ok(Json(serde_json::json!({"votes": 0})))
-            } else {
-                error!(%qid, error = %error, "dynamodb request to vote for question failed");
-                Err(http::StatusCode::INTERNAL_SERVER_ERROR)
-            }
+        Err(store::Error::Conflict) => {
+            warn!(%qid, ?direction, voter = %voter.id, "rejecting repeated vote");
+            Err(http::StatusCode::CONFLICT)
+        }
+        Err(store::Error::NotFound) => {
+            warn!(%eid, %qid, "attempted to vote for non-existing question");
+            Err(http::StatusCode::NOT_FOUND)
         }
         Err(e) => {
-            error!(%qid, error = %e, "dynamodb request to vote for question failed");
+            error!(%qid, error = %e, "request to vote for question failed");
             Err(http::StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
+/// Vote for a question without saying which event it's for, as older clients do.
+///
+/// This takes an extra round trip to look up the question's event first.
+pub(super) async fn vote_question(
+    Path((qid, direction)): Path<(Ulid, UpDown)>,
+    State(dynamo): State<Backend>,
+    voter: Voter,
+) -> Result<(Voter, Json<serde_json::Value>), StatusCode> {
+    let eid = match dynamo.questions(&[qid]).await {
+        Ok(qs) => match qs.first() {
+            Some(q) => q.eid,
+            None => {
+                warn!(%qid, "attempted to vote for non-existing question");
+                return Err(http::StatusCode::NOT_FOUND);
+            }
+        },
+        Err(e) => {
+            error!(%qid, error = %e, "question request for vote failed");
+            return Err(http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    vote(Path((eid, qid, direction)), State(dynamo), voter).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::Secret;
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Ulid::from_string(e["id"].as_str().unwrap()).unwrap();
         let _secret = e["secret"].as_str().unwrap();
+        let asker = Voter::generate(&backend);
         let q1 = crate::ask::ask(
             Path(eid),
             State(backend.clone()),
+            asker.clone(),
             Json(crate::ask::Question {
                 body: "hello world".into(),
                 asker: None,
             }),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .1;
         let qid1 = Ulid::from_string(q1["id"].as_str().unwrap()).unwrap();
         let q2 = crate::ask::ask(
             Path(eid),
             State(backend.clone()),
+            Voter::generate(&backend),
             Json(crate::ask::Question {
                 body: "hello moon".into(),
                 asker: Some("person".into()),
             }),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .1;
         let qid2 = Ulid::from_string(q2["id"].as_str().unwrap()).unwrap();
 
         let check = |qs: serde_json::Value, expect: &[(&Ulid, u64)]| {
@@ -144,44 +206,255 @@ mod tests {
             }
         };
 
-        let _ = super::vote(Path((qid2, UpDown::Up)), State(backend.clone()))
+        let voter = Voter::generate(&backend);
+        let vote = |qid, direction, voter: &Voter| {
+            super::vote(
+                Path((eid, qid, direction)),
+                State(backend.clone()),
+                voter.clone(),
+            )
+        };
+
+        let _ = vote(qid2, UpDown::Up, &voter).await.unwrap();
+        check(
+            crate::list::list(
+                Path(eid),
+                None,
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
             .await
-            .unwrap();
+            .1
+            .unwrap()
+            .0,
+            &[(&qid2, 2), (&qid1, 1)],
+        );
+
+        let _ = vote(qid1, UpDown::Up, &voter).await.unwrap();
+        let _ = vote(qid2, UpDown::Down, &voter).await.unwrap();
         check(
-            crate::list::list(Path(eid), State(backend.clone()))
+            crate::list::list(
+                Path(eid),
+                None,
+                Default::default(),
+                Default::default(),
+                State(backend.clone()),
+            )
+            .await
+            .1
+            .unwrap()
+            .0,
+            &[(&qid1, 2), (&qid2, 1)],
+        );
+
+        // the same voter can't up-vote twice, nor down-vote without having up-voted
+        assert_eq!(
+            vote(qid1, UpDown::Up, &voter).await.unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            vote(qid1, UpDown::Down, &Voter::generate(&backend))
                 .await
-                .1
-                .unwrap()
-                .0,
-            &[(&qid2, 2), (&qid1, 1)],
+                .unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        // but the asker can take back the question's initial vote
+        let (_, votes) = vote(qid1, UpDown::Down, &asker).await.unwrap();
+        assert_eq!(votes["votes"], 1);
+        assert_eq!(
+            vote(qid1, UpDown::Down, &asker).await.unwrap_err(),
+            StatusCode::CONFLICT
         );
+        let qs = crate::list::list(
+            Path(eid),
+            None,
+            Default::default(),
+            Default::default(),
+            State(backend.clone()),
+        )
+        .await
+        .1
+        .unwrap()
+        .0;
+        for q in qs.as_array().unwrap() {
+            assert_eq!(q["votes"], 1, "{q} should be back at one vote");
+        }
 
-        let _ = super::vote(Path((qid1, UpDown::Up)), State(backend.clone()))
+        // voting for a non-existing question gives 404, and doesn't create it
+        let qid = Ulid::from_string("00000000000000000000000001").unwrap();
+        for direction in [UpDown::Up, UpDown::Down] {
+            assert_eq!(
+                vote(qid, direction, &voter).await.unwrap_err(),
+                StatusCode::NOT_FOUND
+            );
+        }
+        assert!(backend.questions(&[qid]).await.unwrap().is_empty());
+
+        // and so does voting for a question through some other event
+        let other = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let other = Ulid::from_string(other["id"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            super::vote(
+                Path((other, qid1, UpDown::Up)),
+                State(backend.clone()),
+                Voter::generate(&backend)
+            )
             .await
-            .unwrap();
-        let _ = super::vote(Path((qid2, UpDown::Down)), State(backend.clone()))
+            .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        backend.delete(&other).await;
+
+        // older clients that don't say which event the question is in can still vote
+        let (_, votes) = super::vote_question(
+            Path((qid1, UpDown::Up)),
+            State(backend.clone()),
+            Voter::generate(&backend),
+        )
+        .await
+        .unwrap();
+        assert_eq!(votes["votes"], 2);
+        let (_, votes) = vote(qid1, UpDown::Down, &voter).await.unwrap();
+        assert_eq!(votes["votes"], 1);
+        assert_eq!(
+            super::vote_question(
+                Path((qid, UpDown::Up)),
+                State(backend.clone()),
+                voter.clone()
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        // once the host closes the event, questions are refused but votes still count
+        let set_state = async |state: &str| {
+            let _ = crate::settings::update(
+                Path(eid),
+                Secret(e["secret"].as_str().unwrap().to_string()),
+                State(backend.clone()),
+                Json(
+                    serde_json::json!({ "state": state })
+                        .as_object()
+                        .unwrap()
+                        .clone(),
+                ),
+            )
             .await
             .unwrap();
-        check(
-            crate::list::list(Path(eid), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
-            &[(&qid1, 2), (&qid2, 1)],
+        };
+        set_state("closed").await;
+        let ask = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            Voter::generate(&backend),
+            Json(crate::ask::Question {
+                body: "hello sun".into(),
+                asker: None,
+            }),
+        )
+        .await;
+        assert_eq!(ask.unwrap_err(), StatusCode::FORBIDDEN);
+        let (_, votes) = vote(qid2, UpDown::Up, &voter).await.unwrap();
+        assert_eq!(votes["votes"], 2);
+
+        // and once it's locked, votes are refused too, but the questions can still be listed
+        set_state("locked").await;
+        assert_eq!(
+            vote(qid1, UpDown::Up, &voter).await.unwrap_err(),
+            StatusCode::FORBIDDEN
         );
+        let (headers, qs) = crate::list::list(
+            Path(eid),
+            None,
+            Default::default(),
+            Default::default(),
+            State(backend.clone()),
+        )
+        .await;
+        check(qs.unwrap().0, &[(&qid2, 2), (&qid1, 1)]);
+        let res = axum::response::IntoResponse::into_response((headers, ()));
+        assert_eq!(res.headers()[http::header::CACHE_CONTROL], "max-age=300");
 
         backend.delete(&eid).await;
     }
 
+    #[tokio::test]
+    async fn voter_cookie() {
+        use axum::response::IntoResponse;
+
+        let parts = |cookie: Option<&str>| {
+            let mut req = http::Request::builder();
+            if let Some(cookie) = cookie {
+                req = req.header(header::COOKIE, cookie);
+            }
+            req.body(()).unwrap().into_parts().0
+        };
+
+        let backend = Backend::local().await;
+        let extract = async |cookie: &str| {
+            Voter::from_request_parts(&mut parts(Some(cookie)), &backend)
+                .await
+                .unwrap()
+        };
+
+        // new voters get a signed token ...
+        let voter = Voter::from_request_parts(&mut parts(None), &backend)
+            .await
+            .unwrap();
+        let id = voter.id;
+        let res = (voter, Json(serde_json::json!({}))).into_response();
+        let cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+        let token = cookie.split(';').next().unwrap().to_string();
+        let signature = token
+            .strip_prefix(&format!("voter={id}."))
+            .expect("token should be the voter id and a signature");
+        assert!(backend.verify(&["voter", &id.to_string()], signature));
+
+        // ... that they then keep using, and that isn't re-issued
+        let again = extract(&format!("a=b; {token}")).await;
+        assert_eq!(again.id, id);
+        let res = (again, Json(serde_json::json!({}))).into_response();
+        assert!(!res.headers().contains_key(header::SET_COOKIE));
+
+        // made-up, unsigned, and forged tokens are all replaced
+        let other = Ulid::generate();
+        for bogus in [
+            "voter=nope".to_string(),
+            format!("voter={id}"),
+            format!("voter={id}.{}", &signature[1..]),
+            format!("voter={other}.{signature}"),
+            format!("voter={other}.{}", "0".repeat(signature.len())),
+        ] {
+            let bogus = extract(&bogus).await;
+            assert_ne!(bogus.id, id);
+            assert_ne!(bogus.id, other);
+            assert!(bogus.cookie.is_some());
+        }
+    }
+
     #[tokio::test]
     async fn local() {
         inner(Backend::local().await).await;
     }
 
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite() {
+        inner(Backend::sqlite(":memory:").await).await;
+    }
+
     #[tokio::test]
     #[ignore]
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() {
+        inner(Backend::postgres().await).await;
+    }
 }
@@ -76,10 +76,10 @@ async fn host_starts_new_q_and_a_session(
         event.get("id").unwrap(),
         &AttributeValue::S(event_id.into())
     );
-    assert_eq!(
-        event.get("secret").unwrap(),
-        &AttributeValue::S(event_secret.into())
-    );
+    // only a salted hash of the secret is stored
+    let stored = event.get("secret").unwrap().as_s().unwrap();
+    assert!(stored.starts_with("sha256$"));
+    assert!(!stored.contains(event_secret));
     // ... and there are actually no questions associated
     // with that event
     assert_eq!(